@@ -1,18 +1,262 @@
 use std::{
     fs::{self, File},
-    io::Write,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use log::warn;
-use reqwest::StatusCode;
+use log::{debug, warn};
+use rand::Rng;
+use reqwest::{
+    header::{
+        HeaderValue, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+        RETRY_AFTER,
+    },
+    Certificate, Client, Proxy, RequestBuilder, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, time::timeout};
+
+use super::provider::{FetchOutcome, RemoteFile};
+
+/// settings of the http client shared by all downloads
+pub struct ClientOptions {
+    /// http(s) proxy all requests are sent through
+    pub proxy: Option<String>,
+    /// pem file with additional certificate authorities to trust
+    pub ca_bundle: Option<String>,
+    pub user_agent: String,
+    pub connect_timeout: Duration,
+    /// how long a request may wait for data before it fails
+    pub read_timeout: Duration,
+    /// delay before the first retry, doubled for every further one
+    pub backoff: Duration,
+    /// maximum number of requests per second across all threads
+    pub max_rps: Option<f64>,
+}
+
+/// upper bound for the delay between retries
+static MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// the http client shared by all downloads, together with how failed requests are retried and how fast requests may be sent
+#[derive(Clone)]
+pub struct HttpContext {
+    pub client: Client,
+    pub max_retries: usize,
+    read_timeout: Duration,
+    backoff: Duration,
+    throttle: Arc<Throttle>,
+}
+
+impl HttpContext {
+    /// builds the shared client from options
+    pub fn new(options: &ClientOptions, max_retries: usize) -> std::io::Result<Self> {
+        let to_io_error =
+            |err: reqwest::Error| std::io::Error::new(std::io::ErrorKind::InvalidInput, err);
+        // the read timeout is applied per response and chunk by send and download_file, as the client only knows a timeout for whole requests
+        let mut builder = Client::builder()
+            .user_agent(&options.user_agent)
+            .connect_timeout(options.connect_timeout);
+        if let Some(proxy) = &options.proxy {
+            builder = builder.proxy(Proxy::all(proxy).map_err(to_io_error)?);
+        }
+        if let Some(ca_bundle) = &options.ca_bundle {
+            for certificate in
+                Certificate::from_pem_bundle(&fs::read(ca_bundle)?).map_err(to_io_error)?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        Ok(Self {
+            client: builder.build().map_err(to_io_error)?,
+            max_retries,
+            read_timeout: options.read_timeout,
+            backoff: options.backoff,
+            throttle: Arc::new(Throttle::new(options.max_rps)),
+        })
+    }
+
+    /// sends request once the shared rate limit allows it. fails if the server does not respond within the read timeout
+    pub async fn send(&self, request: RequestBuilder) -> std::io::Result<Response> {
+        self.throttle.wait().await;
+        match timeout(self.read_timeout, request.send()).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(err)) => Err(std::io::Error::new(std::io::ErrorKind::Other, err)),
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Server did not respond in time",
+            )),
+        }
+    }
+
+    /// waits before retry number current_retry. waits as long as the server asked for with retry_after if it did and that is at most
+    /// MAX_BACKOFF, otherwise backs off exponentially with jitter
+    pub async fn wait_before_retry(&self, current_retry: usize, retry_after: Option<Duration>) {
+        match retry_after {
+            Some(retry_after) if retry_after <= MAX_BACKOFF => {
+                debug!("Server asked to retry after {}s", retry_after.as_secs());
+                // the server is likely rate limiting all of our requests, so every download pauses
+                self.throttle.pause(retry_after);
+            }
+            retry_after => {
+                if let Some(retry_after) = retry_after {
+                    warn!(
+                        "Server asked to retry after {}s, more than the maximum of {}s; Backing off instead",
+                        retry_after.as_secs(),
+                        MAX_BACKOFF.as_secs()
+                    );
+                }
+                let exponent = u32::try_from(current_retry.saturating_sub(1)).unwrap_or(u32::MAX);
+                let delay = self
+                    .backoff
+                    .saturating_mul(2_u32.saturating_pow(exponent))
+                    .min(MAX_BACKOFF);
+                let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+                tokio::time::sleep(delay.mul_f64(jitter)).await;
+            }
+        }
+    }
+}
+
+/// spaces out the requests of all downloads and lets them pause together
+struct Throttle {
+    /// minimal time between two requests, None if unlimited
+    interval: Option<Duration>,
+    /// earliest time the next request may be sent
+    next_slot: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(max_rps: Option<f64>) -> Self {
+        Self {
+            interval: max_rps
+                .filter(|max_rps| *max_rps > 0.0)
+                .and_then(|max_rps| Duration::try_from_secs_f64(1.0 / max_rps).ok()),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
 
-/// downloads a file from file_url and save it to output_name. output folder needs to exist or function will throw error
-pub fn download_file(
+    /// waits until the next request may be sent
+    async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self
+                .next_slot
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval.unwrap_or_default();
+            slot
+        };
+        tokio::time::sleep(slot.saturating_duration_since(Instant::now())).await;
+    }
+
+    /// holds back all requests until delay has passed
+    fn pause(&self, delay: Duration) {
+        let mut next_slot = self
+            .next_slot
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(until) = Instant::now().checked_add(delay) {
+            *next_slot = (*next_slot).max(until);
+        }
+    }
+}
+
+/// returns how long the server asked us to wait if it is rate limiting or unavailable
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    if !matches!(
+        response.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    ) {
+        return None;
+    }
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    // either a number of seconds or a http date
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => parse_http_date(value)?
+            .duration_since(SystemTime::now())
+            .ok(),
+    }
+}
+
+/// parses a http date like Sun, 06 Nov 1994 08:49:37 GMT
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    static MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts = value.split_whitespace().collect::<Vec<&str>>();
+    let [_, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    let day = day.parse::<i64>().ok()?;
+    let month = MONTHS.iter().position(|name| name == month)? as i64 + 1;
+    let year = year.parse::<i64>().ok()?;
+    let time = time
+        .split(':')
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let [hours, minutes, seconds] = time.as_slice() else {
+        return None;
+    };
+    if !(1..=31).contains(&day) || *hours > 23 || *minutes > 59 || *seconds > 60 {
+        return None;
+    }
+
+    // days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+    UNIX_EPOCH.checked_add(Duration::from_secs(
+        days * 86_400 + hours * 3_600 + minutes * 60 + seconds,
+    ))
+}
+
+/// etag and last-modified header of a downloaded file, used to only download it again once it changed
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// reads the validators from the headers of a response, keeping the current ones for headers which are missing
+    fn update(&mut self, response: &Response) {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        self.etag = header(ETAG).or(self.etag.take());
+        self.last_modified = header(LAST_MODIFIED).or(self.last_modified.take());
+    }
+}
+
+/// result of a successful download_file call
+pub struct Download {
+    /// validators of the downloaded file, None if the server reported the cached copy as not modified
+    pub validators: Option<Validators>,
+    /// number of requests it took
+    pub attempts: usize,
+}
+
+/// downloads a file from file_url and save it to output_name. output folder needs to exist or function will throw error.
+/// the body is streamed into a hidden .part file which is resumed on retries and only moved to output_name once complete.
+/// if output_name exists, cached are sent as conditions so it is only downloaded again if it changed
+pub async fn download_file(
     output_name: &Path,
     file_url: &str,
-    max_retries: usize,
-) -> std::io::Result<()> {
+    http: &HttpContext,
+    cached: &Validators,
+) -> std::io::Result<Download> {
     // checks if output folder exists
     match output_name.parent() {
         Some(parent_dir) => {
@@ -30,34 +274,115 @@ pub fn download_file(
             "No parent directory",
         )),
     }?;
-    // deletes output file if exist
-    output_name.exists().then(|| fs::remove_file(output_name));
+    let part_name = part_path(output_name)?;
+    // a leftover from an earlier run may belong to an older version of the file
+    if part_name.exists() {
+        tokio::fs::remove_file(&part_name).await?;
+    }
+
+    let conditional = output_name.exists();
+    let mut validators = Validators::default();
 
-    let mut file = File::create(output_name)?;
-    let client = reqwest::blocking::Client::new();
+    let mut retry_after = None;
 
     // retry until max_retries is reached or download succeeded
-    for current_retry in 0..=max_retries {
-        let response = match client.get(file_url).send() {
+    for current_retry in 0..=http.max_retries {
+        if current_retry > 0 {
+            http.wait_before_retry(current_retry, retry_after.take())
+                .await;
+        }
+
+        // resume from what the previous tries already downloaded
+        let offset = tokio::fs::metadata(&part_name)
+            .await
+            .map_or(0, |metadata| metadata.len());
+        let mut request = http.client.get(file_url);
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        } else if conditional {
+            if let Some(etag) = &cached.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let mut response = match http.send(request).await {
             Ok(response) => response,
             Err(err) => {
                 warn!("Failed to download {file_url} on try {current_retry}: {err}");
                 continue;
             }
         };
+
         // if ok we write to file, otherwise we retry
-        match response.status() {
-            StatusCode::OK => match response.text() {
-                Ok(data) => {
-                    file.write_all(data.as_bytes())?;
-                    return Ok(());
+        let (mut file, expected_length) = match response.status() {
+            // the copy we have is still current
+            StatusCode::NOT_MODIFIED if offset == 0 && conditional => {
+                return Ok(Download {
+                    validators: None,
+                    attempts: current_retry + 1,
+                })
+            }
+            // server sent the whole file, so we start over
+            StatusCode::OK => {
+                validators = Validators::default();
+                validators.update(&response);
+                (
+                    tokio::fs::File::create(&part_name).await?,
+                    response.content_length(),
+                )
+            }
+            StatusCode::PARTIAL_CONTENT => match content_range(&response) {
+                Some((start, total)) if start == offset => {
+                    validators.update(&response);
+                    (
+                        OpenOptions::new().append(true).open(&part_name).await?,
+                        total.or(response.content_length().map(|length| offset + length)),
+                    )
+                }
+                _ => {
+                    warn!("Failed to resume {file_url} on try {current_retry}; Starting over");
+                    tokio::fs::remove_file(&part_name).await?;
+                    continue;
                 }
-                Err(err) => warn!("Failed to download {file_url} on try {current_retry}: {err}"),
             },
-            _ => warn!(
-                "Failed to download {file_url} on try {current_retry}; Statuscode was {}",
-                response.status()
+            status => {
+                warn!(
+                    "Failed to download {file_url} on try {current_retry}; Statuscode was {status}"
+                );
+                retry_after = self::retry_after(&response);
+                // unless we are being rate limited, the partial file is likely the reason for e.g. 416, so we start over
+                if offset > 0
+                    && !matches!(
+                        status,
+                        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                    )
+                {
+                    tokio::fs::remove_file(&part_name).await?;
+                }
+                continue;
+            }
+        };
+
+        if let Err(err) = write_body(&mut response, &mut file, http.read_timeout).await {
+            warn!("Failed to download {file_url} on try {current_retry}: {err}");
+            continue;
+        }
+
+        // only complete files are moved into place
+        let length = file.metadata().await?.len();
+        match expected_length {
+            Some(expected_length) if expected_length != length => warn!(
+                "Failed to download {file_url} on try {current_retry}; Received {length} of {expected_length} bytes"
             ),
+            _ => {
+                tokio::fs::rename(&part_name, output_name).await?;
+                return Ok(Download {
+                    validators: Some(validators),
+                    attempts: current_retry + 1,
+                });
+            }
         }
     }
     Err(std::io::Error::new(
@@ -65,3 +390,351 @@ pub fn download_file(
         "Could not download file?",
     ))
 }
+
+/// streams the body of response into file, failing if no data arrives within read_timeout
+async fn write_body(
+    response: &mut Response,
+    file: &mut tokio::fs::File,
+    read_timeout: Duration,
+) -> std::io::Result<()> {
+    loop {
+        let chunk = timeout(read_timeout, response.chunk())
+            .await
+            .map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "No data received in time")
+            })?
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+        match chunk {
+            Some(chunk) => file.write_all(&chunk).await?,
+            None => return file.flush().await,
+        }
+    }
+}
+
+/// returns the hidden .part file output_name is downloaded to
+fn part_path(output_name: &Path) -> std::io::Result<PathBuf> {
+    let file_name = output_name.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "No file name to download to",
+        )
+    })?;
+    Ok(output_name.with_file_name(format!(".{}.part", file_name.to_string_lossy())))
+}
+
+/// parses the start and, if known, the total length from the Content-Range header (bytes start-end/total)
+fn content_range(response: &Response) -> Option<(u64, Option<u64>)> {
+    let (range, total) = response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('/')?;
+    let start = range.split_once('-')?.0.parse::<u64>().ok()?;
+    Some((start, total.parse::<u64>().ok()))
+}
+
+/// returns the local path if source is a file:// url or a plain path instead of a http(s) url
+pub fn local_path(source: &str) -> Option<PathBuf> {
+    match source {
+        _ if source.starts_with("http://") || source.starts_with("https://") => None,
+        _ => Some(PathBuf::from(
+            source.strip_prefix("file://").unwrap_or(source),
+        )),
+    }
+}
+
+/// the export of a provider publishing all its hashes in one file, like the abuse.ch csv exports
+pub struct Export {
+    /// full, recent, a url or a local copy of an export
+    source: String,
+    /// put in front of the file name the export is saved under, to tell apart the exports of different providers
+    prefix: &'static str,
+    full_url: &'static str,
+    recent_url: &'static str,
+}
+
+impl Export {
+    pub fn new(
+        source: String,
+        prefix: &'static str,
+        full_url: &'static str,
+        recent_url: &'static str,
+    ) -> Self {
+        Self {
+            source,
+            prefix,
+            full_url,
+            recent_url,
+        }
+    }
+
+    /// name under which the export is saved; the files staged from it share its stem
+    pub fn file_name(&self) -> String {
+        match self.source.as_str() {
+            "full" => format!("{}_full.zip", self.prefix),
+            "recent" => format!("{}_recent.csv", self.prefix),
+            source => format!(
+                "{}_{}",
+                self.prefix,
+                Path::new(source.trim_end_matches('/'))
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().into_owned())
+                    .unwrap_or("export".to_owned())
+            ),
+        }
+    }
+
+    /// stem of the file name, which the staged files are named after
+    pub fn stem(&self) -> String {
+        let file_name = self.file_name();
+        Path::new(&file_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or(file_name.clone())
+    }
+
+    /// the export as the only file offered by the provider
+    pub fn remote_files(&self) -> Vec<RemoteFile> {
+        let url = match self.source.as_str() {
+            "full" => self.full_url,
+            "recent" => self.recent_url,
+            source => source,
+        };
+        vec![RemoteFile {
+            url: url.to_owned(),
+            file_name: self.file_name(),
+            // the exports are regenerated regularly
+            immutable: false,
+        }]
+    }
+}
+
+/// fetches an export into the raw folder of output_dir, which keeps it out of the folder insert_files reads. local copies are
+/// parsed where they are
+pub async fn fetch_export(
+    remote_file: &RemoteFile,
+    output_dir: &Path,
+    http: &HttpContext,
+    validators: &Validators,
+) -> std::io::Result<FetchOutcome> {
+    if let Some(path) = local_path(&remote_file.url) {
+        return Ok(FetchOutcome::Fetched {
+            path,
+            validators: Validators::default(),
+            attempts: 1,
+        });
+    }
+    let raw_dir = output_dir.join("raw");
+    fs::create_dir_all(&raw_dir)?;
+    let download_path = raw_dir.join(&remote_file.file_name);
+    let download = download_file(&download_path, &remote_file.url, http, validators).await?;
+    Ok(FetchOutcome::from_download(download_path, download))
+}
+
+/// splits a line of a csv export into its fields, removing quotes and surrounding whitespace
+pub fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            // escaped quote inside a quoted field
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            _ => field.push(char),
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+/// hardlinks source to output_name, falling back to copying if linking is not possible (e.g. across filesystems)
+pub fn link_or_copy(source: &Path, output_name: &Path) -> std::io::Result<()> {
+    // linking a file onto itself would delete it
+    if output_name.exists() {
+        if fs::canonicalize(source)? == fs::canonicalize(output_name)? {
+            return Ok(());
+        }
+        fs::remove_file(output_name)?;
+    }
+    if let Err(err) = fs::hard_link(source, output_name) {
+        debug!(
+            "Could not link {} ({err}), copying instead",
+            source.display()
+        );
+        fs::copy(source, output_name)?;
+    }
+    Ok(())
+}
+
+/// calculates the hex encoded sha256 of a file
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+    use crate::downloader::test_server::{block_on, http_context, response, serve, Request};
+
+    static BODY: &[u8] = b"0123456789abcdefghij";
+
+    /// downloads the file served by handle into a temporary directory, returning the result and the directory
+    fn download(
+        handle: impl Fn(&Request) -> Vec<u8> + Send + 'static,
+        max_retries: usize,
+    ) -> (std::io::Result<Download>, tempfile::TempDir) {
+        let url = format!("{}/file", serve(handle));
+        let dir = tempfile::tempdir().unwrap();
+        let result = block_on(download_file(
+            &dir.path().join("file"),
+            &url,
+            &http_context(max_retries),
+            &Validators::default(),
+        ));
+        (result, dir)
+    }
+
+    /// the first response claims the whole body but is cut off after half of it
+    fn cut_off() -> Vec<u8> {
+        response(
+            "200 OK",
+            &[("Content-Length", BODY.len().to_string())],
+            &BODY[..10],
+        )
+    }
+
+    #[test]
+    fn download_resumes_from_part_file() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let (result, dir) = download(
+            move |request| match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => cut_off(),
+                _ => {
+                    assert_eq!(request.header("range"), Some("bytes=10-"));
+                    response(
+                        "206 Partial Content",
+                        &[("Content-Range", format!("bytes 10-19/{}", BODY.len()))],
+                        &BODY[10..],
+                    )
+                }
+            },
+            1,
+        );
+        assert_eq!(result.unwrap().attempts, 2);
+        assert_eq!(fs::read(dir.path().join("file")).unwrap(), BODY);
+        assert!(!dir.path().join(".file.part").exists());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn download_starts_over_if_range_is_ignored() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let (result, dir) = download(
+            move |_| match requests.fetch_add(1, Ordering::SeqCst) {
+                0 => cut_off(),
+                _ => response("200 OK", &[], BODY),
+            },
+            1,
+        );
+        assert_eq!(result.unwrap().attempts, 2);
+        assert_eq!(fs::read(dir.path().join("file")).unwrap(), BODY);
+    }
+
+    #[test]
+    fn download_rejects_length_mismatch() {
+        let (result, dir) = download(|_| cut_off(), 0);
+        assert!(result.is_err());
+        assert!(!dir.path().join("file").exists());
+
+        // a range which is longer than its body
+        let requests = Arc::new(AtomicUsize::new(0));
+        let (result, dir) = download(
+            move |_| match requests.fetch_add(1, Ordering::SeqCst) {
+                0 => cut_off(),
+                _ => response(
+                    "206 Partial Content",
+                    &[("Content-Range", "bytes 10-14/30".to_owned())],
+                    &BODY[10..15],
+                ),
+            },
+            1,
+        );
+        assert!(result.is_err());
+        assert!(!dir.path().join("file").exists());
+    }
+
+    #[test]
+    fn retry_after_over_limit_is_backed_off() {
+        let http = http_context(0);
+        block_on(async {
+            timeout(
+                Duration::from_secs(5),
+                http.wait_before_retry(1, Some(Duration::MAX)),
+            )
+            .await
+            .unwrap();
+            // requests are not held back either
+            timeout(Duration::from_secs(1), http.throttle.wait())
+                .await
+                .unwrap();
+        });
+
+        // must not overflow
+        let throttle = Throttle::new(Some(1e-300));
+        throttle.pause(Duration::MAX);
+        assert!(throttle.interval.is_none());
+    }
+
+    #[test]
+    fn parse_http_date_reads_imf_fixdate() {
+        let seconds = |value| {
+            parse_http_date(value).map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_secs())
+        };
+        assert_eq!(seconds("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+        assert_eq!(seconds("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        // leap day
+        assert_eq!(
+            seconds("Thu, 29 Feb 2024 12:00:00 GMT"),
+            Some(1_709_208_000)
+        );
+        assert_eq!(
+            seconds("Fri, 01 Mar 2024 00:00:00 GMT"),
+            Some(1_709_251_200)
+        );
+    }
+
+    #[test]
+    fn parse_http_date_rejects_invalid_input() {
+        for value in [
+            "",
+            "120",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37:00 GMT",
+            "Sun, 06 Nov 1969 08:49:37 GMT",
+        ] {
+            assert_eq!(parse_http_date(value), None, "{value}");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::download_commons::{sha256_file, Validators};
+
+/// name of the state file inside the tmp workfolder; hidden so insert_files skips it
+static STATE_FILE: &str = ".fetch_state.json";
+
+/// a file which was fetched completely
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FetchedFile {
+    /// location the file was fetched from
+    pub url: String,
+    /// size of the file after fetching
+    pub size: u64,
+    /// hex encoded sha256 of the file after fetching
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// sent along when fetching the file again, so it is only downloaded if it changed
+    #[serde(flatten)]
+    pub validators: Validators,
+}
+
+/// persistent record of the files in the tmp workfolder which were fetched completely
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct FetchState {
+    files: BTreeMap<String, FetchedFile>,
+}
+
+impl FetchState {
+    /// loads the state from output_dir; starts empty if there is none or it cannot be read
+    pub fn load(output_dir: &Path) -> Self {
+        let state_path = output_dir.join(STATE_FILE);
+        if !state_path.exists() {
+            return Self::default();
+        }
+        File::open(&state_path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                serde_json::from_reader(BufReader::new(file)).map_err(|err| err.to_string())
+            })
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Could not read {}, fetching everything: {err}",
+                    state_path.display()
+                );
+                Self::default()
+            })
+    }
+
+    /// writes the state to output_dir
+    pub fn save(&self, output_dir: &Path) -> std::io::Result<()> {
+        // write to a temporary file first so an interrupted save does not lose the old state
+        let state_path = output_dir.join(STATE_FILE);
+        let tmp_path = output_dir.join(format!("{STATE_FILE}.tmp"));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(tmp_path, state_path)
+    }
+
+    /// checks if file_name was fetched completely and is still present in output_dir unchanged
+    pub fn is_complete(&self, output_dir: &Path, file_name: &str) -> bool {
+        self.files.get(file_name).is_some_and(|fetched_file| {
+            fs::metadata(output_dir.join(file_name))
+                .is_ok_and(|metadata| metadata.len() == fetched_file.size)
+        })
+    }
+
+    /// returns the last complete fetch of file_name if the file in output_dir still has the recorded size and sha256
+    pub fn unchanged(&self, output_dir: &Path, file_name: &str) -> Option<&FetchedFile> {
+        let fetched_file = self.files.get(file_name)?;
+        let path = output_dir.join(file_name);
+        if fs::metadata(&path).ok()?.len() != fetched_file.size {
+            return None;
+        }
+        let sha256 = sha256_file(&path).ok()?;
+        (fetched_file.sha256.as_ref() == Some(&sha256)).then_some(fetched_file)
+    }
+
+    /// returns what is known about the last complete fetch of file_name
+    pub fn get(&self, file_name: &str) -> Option<&FetchedFile> {
+        self.files.get(file_name)
+    }
+
+    /// records file_name as fetched completely
+    pub fn mark_complete(&mut self, file_name: String, fetched_file: FetchedFile) {
+        self.files.insert(file_name, fetched_file);
+    }
+
+    /// forgets about file_name, so it gets fetched again next time
+    pub fn remove(&mut self, file_name: &str) {
+        self.files.remove(file_name);
+    }
+}
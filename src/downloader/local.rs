@@ -0,0 +1,114 @@
+use std::{fs, path::Path};
+
+use log::debug;
+use tokio::task::spawn_blocking;
+
+use super::{
+    download_commons::{link_or_copy, local_path, HttpContext, Validators},
+    provider::{BoxFuture, FetchOutcome, HashProvider, RemoteFile},
+};
+
+/// a local folder or file:// url mirroring the VirusShare md5 hashfiles
+pub struct LocalMirror {
+    /// folder containing the mirrored files
+    source: Option<String>,
+}
+
+impl LocalMirror {
+    pub fn new(source: Option<String>) -> Self {
+        Self { source }
+    }
+
+    /// lists the VirusShare hashfiles in the mirror folder
+    fn list_files(&self) -> std::io::Result<Vec<RemoteFile>> {
+        let source = self
+            .source
+            .as_ref()
+            .and_then(|source| local_path(source))
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "No local folder or file:// url set for the local provider",
+                )
+            })?;
+
+        let mut remote_files = fs::read_dir(&source)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                match file_id(&file_name) {
+                    Some((file_id, extension)) => Some(RemoteFile {
+                        url: entry.path().to_string_lossy().into_owned(),
+                        file_name: format!("vs_{:0>5}.md5{extension}", file_id),
+                        immutable: true,
+                    }),
+                    None => {
+                        debug!("Skipping {file_name} as it is not a VirusShare hashfile");
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<RemoteFile>>();
+        remote_files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        Ok(remote_files)
+    }
+}
+
+impl HashProvider for LocalMirror {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _http: &'a HttpContext,
+    ) -> BoxFuture<'a, std::io::Result<Vec<RemoteFile>>> {
+        Box::pin(async move { self.list_files() })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        remote_file: &'a RemoteFile,
+        output_dir: &'a Path,
+        _http: &'a HttpContext,
+        _validators: &'a Validators,
+    ) -> BoxFuture<'a, std::io::Result<FetchOutcome>> {
+        Box::pin(async move {
+            let source = Path::new(&remote_file.url).to_owned();
+            let output_path = output_dir.join(&remote_file.file_name);
+            // copying may take a while if linking is not possible
+            let link_path = output_path.clone();
+            spawn_blocking(move || link_or_copy(&source, &link_path)).await??;
+            Ok(FetchOutcome::Fetched {
+                path: output_path,
+                validators: Validators::default(),
+                attempts: 1,
+            })
+        })
+    }
+}
+
+/// extensions of compressed hashfiles, which are kept when fetching them
+static COMPRESSED_EXTENSIONS: [&str; 4] = [".gz", ".zip", ".xz", ".zst"];
+
+/// extracts the file id from VirusShare_00000.md5 or vs_00000.md5 names, which may be compressed like vs_00000.md5.gz.
+/// returns it together with the compression extension
+fn file_id(file_name: &str) -> Option<(usize, &str)> {
+    let (file_name, extension) = COMPRESSED_EXTENSIONS
+        .iter()
+        .find_map(|extension| {
+            file_name
+                .strip_suffix(extension)
+                .map(|file_name| (file_name, *extension))
+        })
+        .unwrap_or((file_name, ""));
+    file_name
+        .strip_suffix(".md5")
+        .and_then(|stem| {
+            stem.strip_prefix("VirusShare_")
+                .or_else(|| stem.strip_prefix("vs_"))
+        })
+        .and_then(|file_id| file_id.parse::<usize>().ok())
+        .map(|file_id| (file_id, extension))
+}
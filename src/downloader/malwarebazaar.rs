@@ -0,0 +1,207 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    path::Path,
+};
+
+use log::{info, warn};
+
+use crate::organizer::compression::read_members;
+
+use super::{
+    download_commons::{fetch_export, split_csv_line, Export, HttpContext, Validators},
+    provider::{BoxFuture, FetchOutcome, HashProvider, RemoteFile},
+};
+
+static FULL_URL: &str = "https://bazaar.abuse.ch/export/csv/full/";
+static RECENT_URL: &str = "https://bazaar.abuse.ch/export/csv/recent/";
+
+/// hash columns of the export and the extension of the staged file they are written to
+static HASH_COLUMNS: [(&str, &str); 3] = [
+    ("md5_hash", "md5"),
+    ("sha1_hash", "sha1"),
+    ("sha256_hash", "sha256"),
+];
+/// positions of the hash columns in the export if it has no header
+static DEFAULT_COLUMNS: [usize; 3] = [2, 3, 1];
+/// column holding the malware family a sample was classified as
+static SIGNATURE_COLUMN: &str = "signature";
+/// position of the signature column in the export if it has no header
+static DEFAULT_SIGNATURE_COLUMN: usize = 8;
+
+/// the MalwareBazaar csv exports
+pub struct MalwareBazaar {
+    /// full, recent, a url or a local copy of an export
+    export: Export,
+}
+
+impl MalwareBazaar {
+    pub fn new(source: String) -> Self {
+        Self {
+            export: Export::new(source, "mb", FULL_URL, RECENT_URL),
+        }
+    }
+}
+
+impl HashProvider for MalwareBazaar {
+    fn name(&self) -> &'static str {
+        "malwarebazaar"
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _http: &'a HttpContext,
+    ) -> BoxFuture<'a, std::io::Result<Vec<RemoteFile>>> {
+        let remote_files = self.export.remote_files();
+        Box::pin(async move { Ok(remote_files) })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        remote_file: &'a RemoteFile,
+        output_dir: &'a Path,
+        http: &'a HttpContext,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, std::io::Result<FetchOutcome>> {
+        Box::pin(fetch_export(remote_file, output_dir, http, validators))
+    }
+
+    fn parse(&self, fetched_file: &Path, output_dir: &Path) -> std::io::Result<()> {
+        let stem = self.export.stem();
+        let mut writers = HASH_COLUMNS
+            .iter()
+            .map(|(_, extension)| {
+                File::create(output_dir.join(format!("{stem}.{extension}"))).map(BufWriter::new)
+            })
+            .collect::<std::io::Result<Vec<BufWriter<File>>>>()?;
+
+        // the full dump is a zip archive, local copies may be compressed in other ways
+        let mut samples = 0;
+        read_members(fetched_file, |reader| {
+            samples += stage_csv(reader, &mut writers)?;
+            Ok(())
+        })?;
+
+        for writer in writers.iter_mut() {
+            writer.flush()?;
+        }
+        info!("Staged {samples} samples from {}", fetched_file.display());
+        Ok(())
+    }
+}
+
+/// writes the hash columns of a csv export into the staged files and returns the number of samples read. hashes of samples with
+/// a known signature are followed by it, separated by a tab, which insert_files keeps as signature name. rows lacking a hash
+/// column, like the last one of a cut off export, are skipped
+fn stage_csv(reader: impl BufRead, writers: &mut [impl Write]) -> std::io::Result<usize> {
+    let mut columns = DEFAULT_COLUMNS.to_vec();
+    let mut signature_column = Some(DEFAULT_SIGNATURE_COLUMN);
+    let mut samples = 0;
+    let mut short_rows = 0;
+    for line in reader.lines() {
+        let line = line?;
+        // comments may contain the header, which tells us where the hashes are
+        if let Some(comment) = line.strip_prefix('#') {
+            let header = split_csv_line(comment);
+            if let Some(positions) = HASH_COLUMNS
+                .iter()
+                .map(|(column, _)| header.iter().position(|name| name == column))
+                .collect::<Option<Vec<usize>>>()
+            {
+                columns = positions;
+                signature_column = header.iter().position(|name| name == SIGNATURE_COLUMN);
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_csv_line(&line);
+        if columns.iter().any(|column| *column >= fields.len()) {
+            short_rows += 1;
+            continue;
+        }
+        // samples without a known signature are marked n/a; tabs separate the fields of the staged files
+        let signature = signature_column
+            .and_then(|column| fields.get(column))
+            .filter(|signature| !signature.is_empty() && *signature != "n/a")
+            .map(|signature| signature.replace('\t', " "));
+        for (writer, column) in writers.iter_mut().zip(&columns) {
+            if let Some(hash) = fields.get(*column).filter(|hash| !hash.is_empty()) {
+                match &signature {
+                    Some(signature) => writeln!(writer, "{hash}\t{signature}")?,
+                    None => writeln!(writer, "{hash}")?,
+                }
+            }
+        }
+        samples += 1;
+    }
+    (short_rows > 0).then(|| warn!("Skipped {short_rows} rows missing a hash column"));
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+    static MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+    static SHA1: &str = "3395856ce81f2b7382dee72602f798b642f14140";
+
+    /// stages csv and returns the number of samples together with the md5, sha1 and sha256 staged files
+    fn staged(csv: &str) -> (usize, [String; 3]) {
+        let mut writers = [Vec::new(), Vec::new(), Vec::new()];
+        let samples = stage_csv(csv.as_bytes(), &mut writers).unwrap();
+        (
+            samples,
+            writers.map(|writer| String::from_utf8(writer).unwrap()),
+        )
+    }
+
+    #[test]
+    fn stage_csv_uses_default_columns_without_header() {
+        let (samples, [md5, sha1, sha256]) = staged(&format!(
+            "\"2024-01-01 00:00:00\", \"{SHA256}\", \"{MD5}\", \"{SHA1}\", \"reporter\", \"a.exe\", \"exe\", \"application/x-dosexec\", \"Emotet\"\n"
+        ));
+        assert_eq!(samples, 1);
+        assert_eq!(md5, format!("{MD5}\tEmotet\n"));
+        assert_eq!(sha1, format!("{SHA1}\tEmotet\n"));
+        assert_eq!(sha256, format!("{SHA256}\tEmotet\n"));
+    }
+
+    #[test]
+    fn stage_csv_reads_columns_from_commented_header() {
+        let (samples, [md5, sha1, sha256]) = staged(&format!(
+            "################\n# MalwareBazaar export\n# \"signature\",\"md5_hash\",\"sha256_hash\",\"sha1_hash\"\n\n\"Qakbot\",\"{MD5}\",\"{SHA256}\",\"{SHA1}\"\n"
+        ));
+        assert_eq!(samples, 1);
+        assert_eq!(md5, format!("{MD5}\tQakbot\n"));
+        assert_eq!(sha1, format!("{SHA1}\tQakbot\n"));
+        assert_eq!(sha256, format!("{SHA256}\tQakbot\n"));
+    }
+
+    #[test]
+    fn stage_csv_leaves_out_unknown_signatures() {
+        let (_, [md5, _, _]) = staged(&format!(
+            "# \"md5_hash\",\"sha1_hash\",\"sha256_hash\",\"signature\"\n\"{MD5}\",\"{SHA1}\",\"{SHA256}\",\"n/a\"\n"
+        ));
+        assert_eq!(md5, format!("{MD5}\n"));
+        // a header without signature column
+        let (_, [md5, _, _]) = staged(&format!(
+            "# \"md5_hash\",\"sha1_hash\",\"sha256_hash\"\n\"{MD5}\",\"{SHA1}\",\"{SHA256}\"\n"
+        ));
+        assert_eq!(md5, format!("{MD5}\n"));
+    }
+
+    #[test]
+    fn stage_csv_skips_rows_missing_columns() {
+        let (samples, [md5, sha1, sha256]) = staged(&format!(
+            "# \"md5_hash\",\"sha1_hash\",\"sha256_hash\",\"signature\"\n\"{MD5}\",\"{SHA1}\"\n\"{MD5}\",\"{SHA1}\",\"{SHA256}\",\"Emotet\",\"extra\"\n"
+        ));
+        assert_eq!(samples, 1);
+        assert_eq!(md5, format!("{MD5}\tEmotet\n"));
+        assert_eq!(sha1, format!("{SHA1}\tEmotet\n"));
+        assert_eq!(sha256, format!("{SHA256}\tEmotet\n"));
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{fetch_state::FetchedFile, provider::RemoteFile};
+
+/// name of the manifest inside the tmp workfolder
+pub static MANIFEST_FILE: &str = "fetch_manifest.json";
+
+/// how fetching a file ended
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FetchStatus {
+    /// the file was downloaded
+    Downloaded,
+    /// the server reported the copy fetched before as not modified
+    Unchanged,
+    /// the file is immutable and was fetched completely before
+    Skipped,
+    /// the file could not be fetched
+    Failed,
+}
+
+/// outcome of fetching a single file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// name of the provider the file belongs to
+    pub provider: String,
+    #[serde(flatten)]
+    pub remote_file: RemoteFile,
+    pub status: FetchStatus,
+    /// number of requests made for the file
+    pub attempts: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// why fetching failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ManifestEntry {
+    pub fn new(
+        provider: &str,
+        remote_file: RemoteFile,
+        status: FetchStatus,
+        attempts: usize,
+        fetched_file: Option<&FetchedFile>,
+    ) -> Self {
+        Self {
+            provider: provider.to_owned(),
+            remote_file,
+            status,
+            attempts,
+            size: fetched_file.map(|fetched_file| fetched_file.size),
+            sha256: fetched_file.and_then(|fetched_file| fetched_file.sha256.clone()),
+            error: None,
+        }
+    }
+}
+
+/// report of the last fetch, listing every file and how fetching it ended
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// loads the manifest of the last fetch from output_dir
+    pub fn load(output_dir: &Path) -> std::io::Result<Self> {
+        let manifest_path = output_dir.join(MANIFEST_FILE);
+        let file = File::open(&manifest_path).map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!("Could not open {}: {err}", manifest_path.display()),
+            )
+        })?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// writes the manifest to output_dir
+    pub fn save(&self, output_dir: &Path) -> std::io::Result<()> {
+        let manifest_path = output_dir.join(MANIFEST_FILE);
+        let tmp_path = output_dir.join(format!(".{MANIFEST_FILE}.tmp"));
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(tmp_path, manifest_path)
+    }
+
+    /// returns the entries of files which could not be fetched
+    pub fn failed(&self) -> Vec<&ManifestEntry> {
+        self.files
+            .iter()
+            .filter(|entry| entry.status == FetchStatus::Failed)
+            .collect()
+    }
+}
@@ -1,2 +1,10 @@
 pub mod download_commons;
+pub mod fetch_state;
+pub mod local;
+pub mod malwarebazaar;
+pub mod manifest;
+pub mod provider;
+#[cfg(test)]
+pub mod test_server;
+pub mod threatfox;
 pub mod virusshare;
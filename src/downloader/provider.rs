@@ -0,0 +1,468 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    runtime::Runtime,
+    sync::Semaphore,
+    task::{spawn_blocking, JoinSet},
+};
+
+use super::{
+    download_commons::{download_file, sha256_file, Download, HttpContext, Validators},
+    fetch_state::{FetchState, FetchedFile},
+    local::LocalMirror,
+    malwarebazaar::MalwareBazaar,
+    manifest::{FetchStatus, Manifest, ManifestEntry, MANIFEST_FILE},
+    threatfox::ThreatFox,
+    virusshare::VirusShare,
+};
+
+/// a single file offered by a provider
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RemoteFile {
+    /// location the file is fetched from
+    pub url: String,
+    /// name the file gets in the tmp workfolder
+    pub file_name: String,
+    /// whether the file never changes once published, so a complete copy never needs to be fetched again
+    pub immutable: bool,
+}
+
+/// what fetching a single file resulted in
+pub enum FetchOutcome {
+    /// the file was saved to path; the validators identify this version of it
+    Fetched {
+        path: PathBuf,
+        validators: Validators,
+        attempts: usize,
+    },
+    /// the copy fetched before is still current
+    NotModified { attempts: usize },
+}
+
+impl FetchOutcome {
+    /// converts the result of download_file for a file downloaded to path
+    pub fn from_download(path: PathBuf, download: Download) -> Self {
+        match download.validators {
+            Some(validators) => Self::Fetched {
+                path,
+                validators,
+                attempts: download.attempts,
+            },
+            None => Self::NotModified {
+                attempts: download.attempts,
+            },
+        }
+    }
+}
+
+/// future returned by the async methods of HashProvider, boxed so providers can be used as trait objects
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// a source of hashes which can be fetched into the tmp workfolder
+pub trait HashProvider: Send + Sync {
+    /// name used to select the provider on the commandline
+    fn name(&self) -> &'static str;
+
+    /// lists all files currently offered by the provider
+    fn discover<'a>(
+        &'a self,
+        http: &'a HttpContext,
+    ) -> BoxFuture<'a, std::io::Result<Vec<RemoteFile>>>;
+
+    /// fetches a single file into output_dir. validators belong to the copy fetched before, if there is one
+    fn fetch<'a>(
+        &'a self,
+        remote_file: &'a RemoteFile,
+        output_dir: &'a Path,
+        http: &'a HttpContext,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, std::io::Result<FetchOutcome>> {
+        Box::pin(async move {
+            let download_path = output_dir.join(&remote_file.file_name);
+            let download =
+                download_file(&download_path, &remote_file.url, http, validators).await?;
+            Ok(FetchOutcome::from_download(download_path, download))
+        })
+    }
+
+    /// turns a fetched file into files insert_files can load. files which already contain one hash per line need no parsing.
+    /// runs on a blocking thread, as it may read the whole file
+    fn parse(&self, _fetched_file: &Path, _output_dir: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// provider specific settings taken from the commandline
+pub struct ProviderOptions {
+    /// full, recent, a url or a local copy of a MalwareBazaar export
+    pub malwarebazaar_source: String,
+    /// full, recent, a url or a local copy of a ThreatFox export
+    pub threatfox_source: String,
+    /// folder or file:// url of a local VirusShare mirror
+    pub local_source: Option<String>,
+    /// url of the folder containing the VirusShare files
+    pub virusshare_url: String,
+    /// name of the VirusShare files, with a placeholder for the file id
+    pub virusshare_pattern: String,
+    /// id of the last VirusShare file, skips probing for it
+    pub max_file_id: Option<usize>,
+}
+
+/// returns every provider the builder knows about
+fn registry(options: &ProviderOptions) -> Vec<Arc<dyn HashProvider>> {
+    vec![
+        Arc::new(VirusShare::new(
+            options.virusshare_url.clone(),
+            options.virusshare_pattern.clone(),
+            options.max_file_id,
+        )),
+        Arc::new(MalwareBazaar::new(options.malwarebazaar_source.clone())),
+        Arc::new(ThreatFox::new(options.threatfox_source.clone())),
+        Arc::new(LocalMirror::new(options.local_source.clone())),
+    ]
+}
+
+/// looks up the providers with the given names in the registry
+pub fn get_providers(
+    names: &[String],
+    options: &ProviderOptions,
+) -> std::io::Result<Vec<Arc<dyn HashProvider>>> {
+    let registry = registry(options);
+    names
+        .iter()
+        .map(|name| {
+            registry
+                .iter()
+                .find(|provider| provider.name() == name)
+                .cloned()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Unknown provider {name}"),
+                    )
+                })
+        })
+        .collect()
+}
+
+/// fetches all files from the given providers into output_dir (tmp workfolder). immutable files which were already fetched completely are skipped
+/// and the others only downloaded if they changed, unless refetch is set. fails if any file could not be fetched
+pub fn fetch_all(
+    providers: &[Arc<dyn HashProvider>],
+    output_dir: Arc<PathBuf>,
+    max_downloads: usize,
+    http: &HttpContext,
+    refetch: bool,
+) -> std::io::Result<()> {
+    let start_time = std::time::Instant::now();
+    // creates output folder
+    fs::create_dir_all(output_dir.as_ref())?;
+
+    runtime()?.block_on(async {
+        let mut jobs = Vec::new();
+        for provider in providers {
+            info!("Indexing {}...", provider.name());
+            let remote_files = provider.discover(http).await.map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Could not index {}: {err}", provider.name()),
+                )
+            })?;
+            info!(
+                "Found {} file(s) on {}",
+                remote_files.len(),
+                provider.name()
+            );
+            jobs.extend(
+                remote_files
+                    .into_iter()
+                    .map(|remote_file| (provider.clone(), remote_file)),
+            );
+        }
+
+        let files = fetch_files(jobs, &output_dir, max_downloads, http, refetch).await?;
+        finish_fetch(Manifest { files }, &output_dir, start_time)
+    })
+}
+
+/// fetches the files recorded as failed in the manifest of the last fetch again
+pub fn retry_failed(
+    options: &ProviderOptions,
+    output_dir: Arc<PathBuf>,
+    max_downloads: usize,
+    http: &HttpContext,
+) -> std::io::Result<()> {
+    let start_time = std::time::Instant::now();
+    let manifest = Manifest::load(&output_dir)?;
+
+    let (failed, mut files): (Vec<ManifestEntry>, Vec<ManifestEntry>) = manifest
+        .files
+        .into_iter()
+        .partition(|entry| entry.status == FetchStatus::Failed);
+    info!("Retrying {} failed file(s)...", failed.len());
+    let jobs = failed
+        .into_iter()
+        .map(|entry| {
+            let provider = get_providers(&[entry.provider], options)?.remove(0);
+            Ok((provider, entry.remote_file))
+        })
+        .collect::<std::io::Result<Vec<(Arc<dyn HashProvider>, RemoteFile)>>>()?;
+
+    files.extend(runtime()?.block_on(fetch_files(
+        jobs,
+        &output_dir,
+        max_downloads,
+        http,
+        false,
+    ))?);
+    finish_fetch(Manifest { files }, &output_dir, start_time)
+}
+
+/// builds the runtime the downloads run on, so callers can stay synchronous
+fn runtime() -> std::io::Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+}
+
+/// a file waiting to be fetched
+struct FetchJob {
+    provider: Arc<dyn HashProvider>,
+    remote_file: RemoteFile,
+    /// the last complete fetch of the file, None if it is fetched from scratch
+    previous: Option<FetchedFile>,
+}
+
+/// how fetching a single file ended, returned by each download task
+struct FetchResult {
+    entry: ManifestEntry,
+    /// set if the file was downloaded and parsed
+    fetched_file: Option<FetchedFile>,
+}
+
+/// fetches the files of each provider into output_dir, at most max_downloads at once, and returns how fetching each of them ended
+async fn fetch_files(
+    jobs: Vec<(Arc<dyn HashProvider>, RemoteFile)>,
+    output_dir: &Arc<PathBuf>,
+    max_downloads: usize,
+    http: &HttpContext,
+    refetch: bool,
+) -> std::io::Result<Vec<ManifestEntry>> {
+    let mut state = FetchState::load(output_dir);
+    let mut entries = Vec::new();
+
+    let semaphore = Arc::new(Semaphore::new(max_downloads.max(1)));
+    // dropping the set aborts every download still running
+    let mut tasks = JoinSet::new();
+    let mut unfinished = BTreeMap::new();
+    let mut skipped = 0;
+    for (index, (provider, remote_file)) in jobs.into_iter().enumerate() {
+        if !refetch
+            && remote_file.immutable
+            && state.is_complete(output_dir, &remote_file.file_name)
+        {
+            debug!(
+                "Skipping {} as it is already fetched",
+                remote_file.file_name
+            );
+            entries.push(ManifestEntry::new(
+                provider.name(),
+                remote_file.clone(),
+                FetchStatus::Skipped,
+                0,
+                state.get(&remote_file.file_name),
+            ));
+            skipped += 1;
+            continue;
+        }
+
+        unfinished.insert(index, (provider.name(), remote_file.clone()));
+        let job = FetchJob {
+            // validators of a file which changed locally would keep it from being downloaded again
+            previous: match refetch {
+                true => None,
+                false => state.unchanged(output_dir, &remote_file.file_name).cloned(),
+            },
+            provider,
+            remote_file,
+        };
+        let semaphore = semaphore.clone();
+        let dir = output_dir.clone();
+        let http = http.clone();
+        tasks.spawn(async move {
+            // the semaphore is never closed, so this only waits for a free slot
+            let _permit = semaphore.acquire_owned().await;
+            (index, fetch_file(job, dir, &http).await)
+        });
+    }
+    (skipped > 0).then(|| info!("Skipped {skipped} already fetched file(s)"));
+
+    // collect the results as the downloads finish
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((index, result)) => {
+                unfinished.remove(&index);
+                let file_name = &result.entry.remote_file.file_name;
+                match result.fetched_file {
+                    Some(fetched_file) => state.mark_complete(file_name.clone(), fetched_file),
+                    None if result.entry.status == FetchStatus::Failed => state.remove(file_name),
+                    None => {}
+                }
+                entries.push(result.entry);
+            }
+            Err(err) => error!("Download task did not finish: {err}"),
+        }
+    }
+    // recorded as failed, so --retry-failed picks them up
+    for (provider, remote_file) in unfinished.into_values() {
+        state.remove(&remote_file.file_name);
+        let mut entry = ManifestEntry::new(provider, remote_file, FetchStatus::Failed, 0, None);
+        entry.error = Some("Download task did not finish".to_owned());
+        entries.push(entry);
+    }
+
+    state.save(output_dir)?;
+
+    entries.sort_by(|a, b| {
+        (&a.provider, &a.remote_file.file_name).cmp(&(&b.provider, &b.remote_file.file_name))
+    });
+    Ok(entries)
+}
+
+/// fetches and parses a single file
+async fn fetch_file(job: FetchJob, output_dir: Arc<PathBuf>, http: &HttpContext) -> FetchResult {
+    let FetchJob {
+        provider,
+        remote_file,
+        previous,
+    } = job;
+    let validators = previous
+        .as_ref()
+        .map(|previous| previous.validators.clone())
+        .unwrap_or_default();
+
+    let (attempts, fetched) = match provider
+        .fetch(&remote_file, &output_dir, http, &validators)
+        .await
+    {
+        Ok(FetchOutcome::Fetched {
+            path,
+            validators,
+            attempts,
+        }) => {
+            // parsing and hashing read the whole file, so they run outside of the runtime
+            let (provider, remote_file) = (provider.clone(), remote_file.clone());
+            let completed = spawn_blocking(move || {
+                complete_file(
+                    provider.as_ref(),
+                    &remote_file,
+                    &path,
+                    &output_dir,
+                    validators,
+                )
+            })
+            .await
+            .unwrap_or_else(|err| Err(std::io::Error::new(std::io::ErrorKind::Other, err)));
+            (attempts, completed.map(Some))
+        }
+        Ok(FetchOutcome::NotModified { attempts }) => (attempts, Ok(None)),
+        Err(err) => (http.max_retries + 1, Err(err)),
+    };
+
+    match fetched {
+        Ok(Some(fetched_file)) => {
+            info!("Downloaded {}", remote_file.file_name);
+            FetchResult {
+                entry: ManifestEntry::new(
+                    provider.name(),
+                    remote_file,
+                    FetchStatus::Downloaded,
+                    attempts,
+                    Some(&fetched_file),
+                ),
+                fetched_file: Some(fetched_file),
+            }
+        }
+        Ok(None) => {
+            info!("{} is unchanged", remote_file.file_name);
+            FetchResult {
+                entry: ManifestEntry::new(
+                    provider.name(),
+                    remote_file,
+                    FetchStatus::Unchanged,
+                    attempts,
+                    previous.as_ref(),
+                ),
+                fetched_file: None,
+            }
+        }
+        Err(err) => {
+            error!("Failed to download {}: {err}", remote_file.url);
+            let mut entry = ManifestEntry::new(
+                provider.name(),
+                remote_file,
+                FetchStatus::Failed,
+                attempts,
+                None,
+            );
+            entry.error = Some(err.to_string());
+            FetchResult {
+                entry,
+                fetched_file: None,
+            }
+        }
+    }
+}
+
+/// parses a freshly fetched file and records its size and sha256
+fn complete_file(
+    provider: &dyn HashProvider,
+    remote_file: &RemoteFile,
+    fetched_file: &Path,
+    output_dir: &Path,
+    validators: Validators,
+) -> std::io::Result<FetchedFile> {
+    provider.parse(fetched_file, output_dir)?;
+    Ok(FetchedFile {
+        url: remote_file.url.clone(),
+        size: fs::metadata(fetched_file)?.len(),
+        sha256: Some(sha256_file(fetched_file)?),
+        validators,
+    })
+}
+
+/// writes the manifest and fails if any file could not be fetched
+fn finish_fetch(
+    manifest: Manifest,
+    output_dir: &Path,
+    start_time: std::time::Instant,
+) -> std::io::Result<()> {
+    manifest.save(output_dir)?;
+    info!(
+        "Downloaded files in {}s",
+        std::time::Instant::now()
+            .duration_since(start_time)
+            .as_secs()
+    );
+
+    let failed = manifest.failed().len();
+    match failed {
+        0 => Ok(()),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "{failed} file(s) could not be fetched, see {}; Use --retry-failed to fetch them again",
+                output_dir.join(MANIFEST_FILE).display()
+            ),
+        )),
+    }
+}
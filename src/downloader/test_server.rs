@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+    time::Duration,
+};
+
+use super::download_commons::{ClientOptions, HttpContext};
+
+/// a request received by the test server
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// header names are lowercase
+    headers: HashMap<String, String>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_lowercase()).map(String::as_str)
+    }
+}
+
+/// serves the responses built by handle on a free local port until the test ends and returns its url. every connection is
+/// closed after one response, so a response may also be cut off
+pub fn serve(handle: impl Fn(&Request) -> Vec<u8> + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            // the headers end with an empty line
+            let mut headers = HashMap::new();
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|length| length > 2) {
+                if let Some((name, value)) = header.split_once(':') {
+                    headers.insert(name.trim().to_lowercase(), value.trim().to_owned());
+                }
+                header.clear();
+            }
+            let mut parts = request_line.split_whitespace();
+            let request = Request {
+                method: parts.next().unwrap_or_default().to_owned(),
+                path: parts.next().unwrap_or_default().to_owned(),
+                headers,
+            };
+            let _ = (&stream).write_all(&handle(&request));
+        }
+    });
+    format!("http://{address}")
+}
+
+/// builds a response with the given status, like 200 OK, headers and body. the content length is the length of body unless
+/// given in headers
+pub fn response(status: &str, headers: &[(&str, String)], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {status}\r\nConnection: close\r\n");
+    if !headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+    {
+        response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// serves files by path, answering 404 for all other paths. HEAD requests get the headers only
+pub fn serve_files(files: HashMap<String, String>) -> String {
+    serve(move |request| match files.get(&request.path) {
+        Some(body) => {
+            let mut response = response("200 OK", &[], body.as_bytes());
+            if request.method == "HEAD" {
+                response.truncate(response.len() - body.len());
+            }
+            response
+        }
+        None => response("404 Not Found", &[], b""),
+    })
+}
+
+/// a client retrying max_retries times with short delays
+pub fn http_context(max_retries: usize) -> HttpContext {
+    HttpContext::new(
+        &ClientOptions {
+            proxy: None,
+            ca_bundle: None,
+            user_agent: "signature-builder-test".to_owned(),
+            connect_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(5),
+            backoff: Duration::from_millis(10),
+            max_rps: None,
+        },
+        max_retries,
+    )
+    .unwrap()
+}
+
+/// runs future to completion
+pub fn block_on<T>(future: impl std::future::Future<Output = T>) -> T {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
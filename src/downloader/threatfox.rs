@@ -0,0 +1,273 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    path::Path,
+};
+
+use log::info;
+use serde::Deserialize;
+
+use crate::organizer::compression::read_members;
+
+use super::{
+    download_commons::{fetch_export, split_csv_line, Export, HttpContext, Validators},
+    provider::{BoxFuture, FetchOutcome, HashProvider, RemoteFile},
+};
+
+static FULL_URL: &str = "https://threatfox.abuse.ch/export/csv/full/";
+static RECENT_URL: &str = "https://threatfox.abuse.ch/export/csv/recent/";
+
+/// ioc types which are file hashes; all others like urls or domains are dropped
+static HASH_TYPES: [&str; 2] = ["md5_hash", "sha256_hash"];
+/// columns of the csv export we need
+static COLUMNS: [&str; 4] = [
+    "ioc_value",
+    "ioc_type",
+    "malware_printable",
+    "confidence_level",
+];
+/// positions of the columns in the csv export if it has no header
+static DEFAULT_COLUMNS: [usize; 4] = [2, 3, 7, 9];
+
+/// a single ioc of the json export
+#[derive(Deserialize)]
+struct Ioc {
+    ioc_value: String,
+    ioc_type: String,
+    malware_printable: Option<String>,
+    confidence_level: Option<u8>,
+}
+
+/// the ThreatFox ioc exports, of which only the file hashes are kept
+pub struct ThreatFox {
+    /// full, recent, a url or a local copy of a csv or json export
+    export: Export,
+}
+
+impl ThreatFox {
+    pub fn new(source: String) -> Self {
+        Self {
+            export: Export::new(source, "tf", FULL_URL, RECENT_URL),
+        }
+    }
+}
+
+impl HashProvider for ThreatFox {
+    fn name(&self) -> &'static str {
+        "threatfox"
+    }
+
+    fn discover<'a>(
+        &'a self,
+        _http: &'a HttpContext,
+    ) -> BoxFuture<'a, std::io::Result<Vec<RemoteFile>>> {
+        let remote_files = self.export.remote_files();
+        Box::pin(async move { Ok(remote_files) })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        remote_file: &'a RemoteFile,
+        output_dir: &'a Path,
+        http: &'a HttpContext,
+        validators: &'a Validators,
+    ) -> BoxFuture<'a, std::io::Result<FetchOutcome>> {
+        Box::pin(fetch_export(remote_file, output_dir, http, validators))
+    }
+
+    fn parse(&self, fetched_file: &Path, output_dir: &Path) -> std::io::Result<()> {
+        let stem = self.export.stem();
+        // hash, malware family and confidence separated by tabs, which insert_files keeps as signature
+        let mut writer = BufWriter::new(File::create(output_dir.join(format!("{stem}.tsv")))?);
+
+        // the full export is a zip archive, local copies may be csv or json
+        let mut iocs = 0;
+        read_members(fetched_file, |reader| {
+            iocs += stage_export(reader, &mut writer)?;
+            Ok(())
+        })?;
+
+        writer.flush()?;
+        info!("Staged {iocs} file hashes from {}", fetched_file.display());
+        Ok(())
+    }
+}
+
+/// writes a file hash ioc as line of the staged file if it is one
+fn stage_ioc(
+    writer: &mut impl Write,
+    hash: &str,
+    ioc_type: &str,
+    family: Option<&str>,
+    confidence: Option<u8>,
+) -> std::io::Result<bool> {
+    if !HASH_TYPES.contains(&ioc_type) || hash.is_empty() {
+        return Ok(false);
+    }
+    // tabs separate the fields of the staged file
+    let family = family.unwrap_or_default().replace('\t', " ");
+    let confidence = confidence
+        .map(|confidence| confidence.to_string())
+        .unwrap_or_default();
+    writeln!(writer, "{hash}\t{family}\t{confidence}")?;
+    Ok(true)
+}
+
+/// writes the file hashes of a csv or json export into the staged file and returns how many there were
+fn stage_export(reader: &mut dyn BufRead, writer: &mut impl Write) -> std::io::Result<usize> {
+    let is_json = reader
+        .fill_buf()?
+        .iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        == Some(&b'{');
+    match is_json {
+        true => stage_json(reader, writer),
+        false => stage_csv(reader, writer),
+    }
+}
+
+/// writes the file hashes of a csv export into the staged file and returns how many there were
+fn stage_csv(reader: &mut dyn BufRead, writer: &mut impl Write) -> std::io::Result<usize> {
+    let mut columns = DEFAULT_COLUMNS.to_vec();
+    let mut iocs = 0;
+    for line in reader.lines() {
+        let line = line?;
+        // comments may contain the header, which tells us where the columns are
+        if let Some(comment) = line.strip_prefix('#') {
+            let header = split_csv_line(comment);
+            if let Some(positions) = COLUMNS
+                .iter()
+                .map(|column| header.iter().position(|name| name == column))
+                .collect::<Option<Vec<usize>>>()
+            {
+                columns = positions;
+            }
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = split_csv_line(&line);
+        let field = |index: usize| {
+            fields
+                .get(columns[index])
+                .map(String::as_str)
+                .filter(|field| !field.is_empty())
+        };
+        if stage_ioc(
+            writer,
+            field(0).unwrap_or_default(),
+            field(1).unwrap_or_default(),
+            field(2),
+            field(3).and_then(|confidence| confidence.parse::<u8>().ok()),
+        )? {
+            iocs += 1;
+        }
+    }
+    Ok(iocs)
+}
+
+/// writes the file hashes of a json export, which maps ioc ids to lists of iocs, into the staged file and returns how many there were
+fn stage_json(reader: &mut dyn BufRead, writer: &mut impl Write) -> std::io::Result<usize> {
+    let export: BTreeMap<String, Vec<Ioc>> = serde_json::from_reader(reader)?;
+    let mut iocs = 0;
+    for ioc in export.values().flatten() {
+        if stage_ioc(
+            writer,
+            &ioc.ioc_value,
+            &ioc.ioc_type,
+            ioc.malware_printable.as_deref(),
+            ioc.confidence_level,
+        )? {
+            iocs += 1;
+        }
+    }
+    Ok(iocs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+    static SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+
+    /// stages export and returns the number of iocs and the staged lines
+    fn staged(export: &str) -> (usize, Vec<String>) {
+        let mut writer = Vec::new();
+        let iocs = stage_export(&mut export.as_bytes(), &mut writer).unwrap();
+        let lines = String::from_utf8(writer)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        (iocs, lines)
+    }
+
+    #[test]
+    fn stage_csv_keeps_only_file_hashes() {
+        let export = format!(
+            r#"################################################################
+# ThreatFox IOCs: CSV export                                   #
+################################################################
+#
+# "first_seen_utc","ioc_id","ioc_value","ioc_type","threat_type","fk_malware","malware_alias","malware_printable","last_seen_utc","confidence_level","reference","tags","anonymous","reporter"
+"2026-10-17 04:00:00", "1", "{MD5}", "md5_hash", "payload", "win.agent", "", "Agent, Tesla", "", "75", "", "", "0", "someone"
+"2026-10-17 04:00:00", "2", "{SHA256}", "sha256_hash", "payload", "win.agent", "", "Agent{tab}Tesla", "", "", "", "", "0", "someone"
+"2026-10-17 04:00:00", "3", "http://malware.test/payload.exe", "url", "payload_delivery", "win.agent", "", "Agent", "", "100", "", "", "0", "someone"
+"2026-10-17 04:00:00", "4", "malware.test", "domain", "botnet_cc", "win.agent", "", "Agent", "", "100", "", "", "0", "someone"
+"2026-10-17 04:00:00", "5", "192.0.2.1:443", "ip:port", "botnet_cc", "win.agent", "", "Agent", "", "100", "", "", "0", "someone"
+"2026-10-17 04:00:00", "6", "", "md5_hash", "payload", "win.agent", "", "Agent", "", "100", "", "", "0", "someone"
+"#,
+            tab = '\t'
+        );
+        assert_eq!(
+            staged(&export),
+            (
+                2,
+                vec![
+                    format!("{MD5}\tAgent, Tesla\t75"),
+                    format!("{SHA256}\tAgent Tesla\t")
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn stage_csv_uses_default_columns_without_header() {
+        let export = format!(
+            r#""2026-10-17 04:00:00", "1", "{MD5}", "md5_hash", "payload", "win.agent", "", "Agent", "", "50", "", "", "0", "someone""#
+        );
+        assert_eq!(staged(&export), (1, vec![format!("{MD5}\tAgent\t50")]));
+    }
+
+    #[test]
+    fn stage_json_keeps_only_file_hashes() {
+        // leading whitespace does not hide the json
+        let export = format!(
+            r#"
+  {{
+    "1": [{{"ioc_value": "{MD5}", "ioc_type": "md5_hash", "malware_printable": "Agent", "confidence_level": 75}}],
+    "2": [{{"ioc_value": "{SHA256}", "ioc_type": "sha256_hash", "malware_printable": null, "confidence_level": null}}],
+    "3": [{{"ioc_value": "http://malware.test/", "ioc_type": "url", "malware_printable": "Agent", "confidence_level": 100}}],
+    "4": [{{"ioc_value": "malware.test", "ioc_type": "domain", "malware_printable": "Agent", "confidence_level": 100}}],
+    "5": [{{"ioc_value": "192.0.2.1:443", "ioc_type": "ip:port", "malware_printable": "Agent", "confidence_level": 100}}]
+  }}"#
+        );
+        assert_eq!(
+            staged(&export),
+            (
+                2,
+                vec![format!("{MD5}\tAgent\t75"), format!("{SHA256}\t\t")]
+            )
+        );
+    }
+
+    #[test]
+    fn stage_json_fails_on_invalid_json() {
+        let mut writer = Vec::new();
+        assert!(stage_export(&mut "{\"1\": [".as_bytes(), &mut writer).is_err());
+    }
+}
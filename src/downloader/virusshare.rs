@@ -1,107 +1,341 @@
-use std::{fs, path::PathBuf, sync::Arc};
-
-use super::download_commons::download_file;
-use log::{error, info, trace, warn};
+use super::{
+    download_commons::{retry_after, HttpContext},
+    provider::{BoxFuture, HashProvider, RemoteFile},
+};
+use log::{debug, trace, warn};
 use reqwest::StatusCode;
-use threadpool_rs::threadpool::pool::ThreadPool;
-
-static URL: &str = "https://virusshare.com/hashfiles/VirusShare_";
-
-/// downloads all files from provider into output_dir (tmp workfolder)
-pub fn download_all(
-    output_dir: Arc<PathBuf>,
-    max_threads: usize,
-    max_retries: usize,
-) -> std::io::Result<()> {
-    let start_time = std::time::Instant::now();
-    // creates output folder
-    fs::create_dir_all(output_dir.as_ref())?;
-
-    info!("Indexing webfiles...");
-    let filecount = match get_file_count(max_retries) {
-        Ok(filecount) => filecount,
-        Err(err) => {
+
+/// highest file id probed for. a provider answering every request with a file, like a misconfigured mirror, would otherwise be
+/// probed forever
+static MAX_PROBED_FILE_ID: usize = 1 << 20;
+
+/// the VirusShare md5 hashfiles
+pub struct VirusShare {
+    /// url of the folder containing the files
+    base_url: String,
+    /// name of the files on the provider; {} or e.g. {:0>5} is replaced with the file id
+    pattern: String,
+    /// id of the last file to fetch; probed from the provider if not set
+    max_file_id: Option<usize>,
+}
+
+impl VirusShare {
+    pub fn new(base_url: String, pattern: String, max_file_id: Option<usize>) -> Self {
+        Self {
+            base_url,
+            pattern,
+            max_file_id,
+        }
+    }
+
+    /// returns the url of the file with file_id
+    fn file_url(&self, file_id: usize) -> std::io::Result<String> {
+        let file_name = format_pattern(&self.pattern, file_id)?;
+        Ok(match self.base_url.ends_with('/') {
+            true => format!("{}{file_name}", self.base_url),
+            false => format!("{}/{file_name}", self.base_url),
+        })
+    }
+
+    /// finds the id of the last file present on provider by doubling the probed id until a file is missing and then binary searching
+    async fn get_last_file_id(&self, http: &HttpContext) -> std::io::Result<usize> {
+        if !self.file_exists(http, 0).await? {
             return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Could not get maximum filecount: {err}"),
-            ))
+                std::io::ErrorKind::NotFound,
+                "Provider does not have any files",
+            ));
         }
-    };
-    info!("Found {filecount} file(s)");
-    // multithreaded download
-    let pool = ThreadPool::new(max_threads)?;
-    for file_id in 0..=filecount {
-        let dir = output_dir.clone();
-        pool.execute(move || {
-            let download_path = dir.join(format!("vs_{:0>5}.md5", file_id));
-            let file_url = format!("{URL}{:0>5}.md5", file_id);
-            match download_file(&download_path, &file_url, max_retries) {
-                Ok(_) => info!("Downloaded {}", download_path.display()),
-                Err(err) => error!("Failed to download {file_url}: {err}"),
-            };
-        });
+
+        // go up in powers of two until we find a missing file
+        let mut present = 0;
+        let mut missing = 1;
+        while self.file_exists(http, missing).await? {
+            present = missing;
+            missing = missing
+                .checked_mul(2)
+                .filter(|missing| *missing <= MAX_PROBED_FILE_ID)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Provider has a file for every id up to {present}, aborting; Check the url and pattern or set --max-file-id?"
+                        ),
+                    )
+                })?;
+        }
+
+        // the last file lies somewhere between the last present and the first missing one
+        while missing - present > 1 {
+            let middle = present + (missing - present) / 2;
+            match self.file_exists(http, middle).await? {
+                true => present = middle,
+                false => missing = middle,
+            }
+        }
+        Ok(present)
     }
-    // wait for files to finish downloading
-    drop(pool);
-
-    info!(
-        "Downloaded files in {}s",
-        std::time::Instant::now()
-            .duration_since(start_time)
-            .as_secs()
-    );
-    Ok(())
-}
 
-/// calculates the total number of files present on provider
-fn get_file_count(base_max_retry: usize) -> Result<usize, reqwest::Error> {
-    let client = reqwest::blocking::Client::new();
-    let mut max = 0;
-    let mut max_retry = base_max_retry;
-
-    // go up in 10 increments
-    loop {
-        let file_url = format!("{URL}{:0>5}.md5", max);
-        trace!("Requesting {}", file_url);
-        let response = client.head(file_url).send()?;
-        match response.status() {
-            StatusCode::OK => max += 10,
-            StatusCode::NOT_FOUND => break,
-            _ => {
-                warn!(
-                    "Received invalid status {}, trying again...",
-                    response.status()
-                );
-                max_retry -= 1;
-                if max_retry == 0 {
-                    warn!("Failed 5 times, aborting; Check your network?")
-                }
+    /// checks if the file with file_id is present on provider. gives up with an error after max_retries failed requests
+    async fn file_exists(&self, http: &HttpContext, file_id: usize) -> std::io::Result<bool> {
+        let file_url = self.file_url(file_id)?;
+        let mut wait = None;
+        for current_retry in 0..=http.max_retries {
+            if current_retry > 0 {
+                http.wait_before_retry(current_retry, wait.take()).await;
+            }
+            trace!("Requesting {}", file_url);
+            match http.send(http.client.head(&file_url)).await {
+                Ok(response) => match response.status() {
+                    StatusCode::OK => return Ok(true),
+                    StatusCode::NOT_FOUND => return Ok(false),
+                    status => {
+                        warn!(
+                            "Received invalid status {status} for {file_url} on try {current_retry}, trying again..."
+                        );
+                        wait = retry_after(&response);
+                    }
+                },
+                Err(err) => warn!("Failed to request {file_url} on try {current_retry}: {err}"),
             }
         }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            format!(
+                "Failed {} times to check {file_url}, aborting; Check your network?",
+                http.max_retries + 1
+            ),
+        ))
+    }
+}
+
+impl HashProvider for VirusShare {
+    fn name(&self) -> &'static str {
+        "virusshare"
     }
 
-    max -= 10;
-    max_retry = base_max_retry;
-
-    // go up in 1 increments from last 10th still present
-    loop {
-        let file_url = format!("{URL}{:0>5}.md5", max);
-        trace!("Requesting {}", file_url);
-        let response = client.head(file_url).send()?;
-        match response.status() {
-            StatusCode::OK => max += 1,
-            StatusCode::NOT_FOUND => break,
-            _ => {
-                warn!(
-                    "Received invalid status {}, trying again...",
-                    response.status()
-                );
-                max_retry -= 1;
-                if max_retry == 0 {
-                    warn!("Failed 5 times, aborting; Check your network?")
+    fn discover<'a>(
+        &'a self,
+        http: &'a HttpContext,
+    ) -> BoxFuture<'a, std::io::Result<Vec<RemoteFile>>> {
+        Box::pin(async move {
+            let last_file_id = match self.max_file_id {
+                Some(max_file_id) => {
+                    debug!("Using {max_file_id} as last file id, skipping probing");
+                    max_file_id
                 }
-            }
+                None => self.get_last_file_id(http).await.map_err(|err| {
+                    std::io::Error::new(
+                        err.kind(),
+                        format!("Could not get maximum filecount: {err}"),
+                    )
+                })?,
+            };
+            (0..=last_file_id)
+                .map(|file_id| {
+                    Ok(RemoteFile {
+                        url: self.file_url(file_id)?,
+                        file_name: format!("vs_{:0>5}.md5", file_id),
+                        immutable: true,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// replaces the placeholder in pattern with file_id. supports {} and zero padding like {:0>5}
+pub fn format_pattern(pattern: &str, file_id: usize) -> std::io::Result<String> {
+    let invalid = || {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Pattern {pattern} needs a {{}} or {{:0>width}} placeholder"),
+        )
+    };
+    let (prefix, rest) = pattern.split_once('{').ok_or_else(invalid)?;
+    let (placeholder, suffix) = rest.split_once('}').ok_or_else(invalid)?;
+    let width = match placeholder {
+        "" => 0,
+        _ => placeholder
+            .strip_prefix(":0>")
+            .and_then(|width| width.parse::<usize>().ok())
+            .ok_or_else(invalid)?,
+    };
+    Ok(format!("{prefix}{file_id:0>width$}{suffix}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        downloader::{
+            provider::fetch_all,
+            test_server::{http_context, response, serve, serve_files},
+        },
+        organizer::{
+            files::{insert_files, write_files},
+            normalize::IngestOptions,
+        },
+    };
+
+    #[test]
+    fn format_pattern_replaces_placeholder() {
+        assert_eq!(
+            format_pattern("VirusShare_{}.md5", 7).unwrap(),
+            "VirusShare_7.md5"
+        );
+        assert_eq!(
+            format_pattern("VirusShare_{:0>5}.md5", 7).unwrap(),
+            "VirusShare_00007.md5"
+        );
+        assert_eq!(format_pattern("{:0>2}", 123).unwrap(), "123");
+    }
+
+    #[test]
+    fn format_pattern_rejects_invalid_placeholders() {
+        for pattern in [
+            "VirusShare.md5",
+            "VirusShare_{.md5",
+            "{:>5}",
+            "{:0>x}",
+            "{0}",
+        ] {
+            let err = format_pattern(pattern, 1).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn file_url_joins_base_url_and_file_name() {
+        for base_url in ["http://mirror/hashfiles", "http://mirror/hashfiles/"] {
+            let provider = VirusShare::new(base_url.to_owned(), "VS_{:0>5}.md5".to_owned(), None);
+            assert_eq!(
+                provider.file_url(12).unwrap(),
+                "http://mirror/hashfiles/VS_00012.md5"
+            );
+        }
+    }
+
+    #[test]
+    fn fetch_insert_export() {
+        // three files of four hashes each, uppercase in the second one
+        let hashes = (0..12)
+            .map(|hash| format!("{hash:0>32x}"))
+            .collect::<Vec<String>>();
+        let files = hashes
+            .chunks(4)
+            .enumerate()
+            .map(|(file_id, chunk)| {
+                let lines = chunk
+                    .iter()
+                    .map(|hash| match file_id {
+                        1 => hash.to_uppercase(),
+                        _ => hash.clone(),
+                    })
+                    .collect::<Vec<String>>();
+                (
+                    format!("/hashfiles/VS_{file_id:0>5}.md5"),
+                    format!("# VirusShare file {file_id}\n{}\n", lines.join("\n")),
+                )
+            })
+            .collect();
+        let base_url = format!("{}/hashfiles/", serve_files(files));
+
+        let dir = tempfile::tempdir().unwrap();
+        let tmp_dir = dir.path().join("tmp");
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let http = http_context(0);
+        let provider: Arc<dyn HashProvider> =
+            Arc::new(VirusShare::new(base_url, "VS_{:0>5}.md5".to_owned(), None));
+        fetch_all(&[provider], Arc::new(tmp_dir.clone()), 2, &http, false).unwrap();
+        for file_id in 0..3 {
+            assert!(tmp_dir.join(format!("vs_{file_id:0>5}.md5")).is_file());
         }
+        assert!(!tmp_dir.join("vs_00003.md5").exists());
+
+        let options = IngestOptions {
+            hash_type: None,
+            rejects_file: path("rejects.txt"),
+            strict: true,
+        };
+        insert_files(
+            tmp_dir.to_string_lossy().into_owned(),
+            2,
+            path("hashes_db"),
+            "hashes".to_owned(),
+            &options,
+        )
+        .unwrap();
+        write_files(
+            path("output"),
+            5,
+            path("hashes_db"),
+            "hashes".to_owned(),
+            path("allowlist_db"),
+            false,
+            false,
+        )
+        .unwrap();
+
+        let mut exported = Vec::new();
+        for file in ["00000", "00001", "00002"] {
+            let content = fs::read_to_string(dir.path().join("output").join(file)).unwrap();
+            exported.extend(content.lines().map(str::to_owned));
+        }
+        assert!(!dir.path().join("output/00003").exists());
+        exported.sort();
+        assert_eq!(exported, hashes);
+        assert!(!dir.path().join("rejects.txt").exists());
+    }
+
+    #[test]
+    fn changed_local_file_is_downloaded_without_validators() {
+        let body = format!("{:0>32x}\n", 1);
+        let conditional_requests = Arc::new(AtomicUsize::new(0));
+        let counter = conditional_requests.clone();
+        let served = body.clone();
+        let base_url = serve(move |request| match request.header("if-none-match") {
+            Some("\"v1\"") => {
+                counter.fetch_add(1, Ordering::SeqCst);
+                response("304 Not Modified", &[], b"")
+            }
+            _ => response(
+                "200 OK",
+                &[("ETag", "\"v1\"".to_owned())],
+                served.as_bytes(),
+            ),
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let tmp_dir = Arc::new(dir.path().to_path_buf());
+        let file = dir.path().join("vs_00000.md5");
+        let http = http_context(0);
+        let providers: [Arc<dyn HashProvider>; 1] = [Arc::new(VirusShare::new(
+            format!("{base_url}/hashfiles/"),
+            "VS_{:0>5}.md5".to_owned(),
+            Some(0),
+        ))];
+        let fetch = || fetch_all(&providers, tmp_dir.clone(), 1, &http, false);
+
+        fetch().unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), body);
+
+        // a truncated copy must not be kept by a 304
+        fs::write(&file, &body[..10]).unwrap();
+        fetch().unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), body);
+
+        // same size, different content
+        fs::write(&file, body.to_uppercase()).unwrap();
+        fetch().unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), body);
+        assert_eq!(conditional_requests.load(Ordering::SeqCst), 0);
     }
-    Ok(max - 1)
 }
@@ -1,13 +1,24 @@
-use std::{fs, path::Path, process::exit, sync::Arc};
+use std::{fs, path::Path, process::exit, sync::Arc, time::Duration};
 
 use cali::parser::Parser;
 use log::{debug, error, info};
 
 use crate::{
-    downloader::virusshare::download_all,
+    downloader::{
+        download_commons::{ClientOptions, HttpContext},
+        provider::{fetch_all, get_providers, retry_failed, ProviderOptions},
+        virusshare::format_pattern,
+    },
     organizer::{
-        database::{cleanup_table, create_pool, get_hash_count, remove_duplicates},
-        files::{insert_file, insert_files, patch, set_timestamp, write_files},
+        allowlist::load_allowlist,
+        database::{
+            attach_allowlist, cleanup_table, create_pool, get_allowed_count, get_hash_count,
+            get_hash_occurrences, get_hash_type_counts, get_provenance, is_allowed,
+            remove_duplicates, HASH_TYPES,
+        },
+        files::{insert_file, insert_files, patch, set_timestamp, write_files, write_yara},
+        migrations::{get_hash_tables, get_schema_version, latest_schema_version},
+        normalize::{clean_line, normalize_hash, IngestOptions},
     },
 };
 
@@ -17,13 +28,31 @@ mod organizer;
 static TMP_DIR: &str = "tmp";
 static MAX_THREADS: usize = 20;
 static MAX_RETRIES: usize = 5;
+static PROVIDERS: &str = "virusshare";
+static MALWAREBAZAAR_SOURCE: &str = "full";
+static THREATFOX_SOURCE: &str = "full";
+static VIRUSSHARE_URL: &str = "https://virusshare.com/hashfiles/";
+static VIRUSSHARE_PATTERN: &str = "VirusShare_{:0>5}.md5";
+static USER_AGENT: &str = concat!(
+    "signature-builder/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/Raspirus/signature-builder)"
+);
+static CONNECT_TIMEOUT: u64 = 10;
+static READ_TIMEOUT: u64 = 30;
+static BACKOFF: u64 = 1000;
+static MIN_RPS: f64 = 0.001;
 
 static DATABASE: &str = "hashes_db";
+static ALLOWLIST_DATABASE: &str = "allowlist_db";
 static TABLE_NAME: &str = "hashes";
 static MAX_FILE_COMBINES: usize = 8;
+static REJECTS_FILE: &str = "rejects.txt";
 
 static FILE_SIZE: usize = 1_000_000;
 static OUTPUT_DIR: &str = "./hashes";
+static RULES_PER_FILE: usize = 10_000;
+static YARA_OUTPUT_DIR: &str = "./yara";
 
 fn main() -> std::io::Result<()> {
     pretty_env_logger::formatted_timed_builder()
@@ -39,25 +68,53 @@ fn main() -> std::io::Result<()> {
         .add_arg("f", "fetch", "Fetches the latest files", false, false)
         .add_arg("i", "insert", "Inserts files into db", false, false)
         .add_arg("e", "export", "Exports all hashes from db", false, false)
+        .add_arg("y", "export-yara", "Exports all hashes from db as YARA rules", false, false)
         .add_arg("if", "insert-file", "Inserts specified file", true, false)
         .add_arg("u", "update", "Fetches and imports", false, false)
+        .add_arg("st", "strict", "Fails inserting if any line holds no valid hash", false, false)
+        .add_arg("sht", "split-hash-types", "Exports the hashes of each hash type into its own subfolder like md5 or sha256", false, false)
+        .add_arg("wn", "with-names", "Exports each hash followed by its signature name, separated by a tab", false, false)
+        .add_arg("rf", "refetch", "Fetches all files again, even if they were fetched before", false, false)
+        .add_arg("rtf", "retry-failed", "Fetches the files which failed during the last fetch again", false, false)
         .add_arg("cdb", "clean-database", "Clears the database", false, false)
         .add_arg("ct", "clean-temp", "Clears the temporary folder", false, false)
         .add_arg("cd", "clean-data", "Clears the table", false, false)
         .add_arg("p", "patch", "Apply a patch file", true, false)
+        .add_arg("al", "allowlist", "Adds the known-good hashes of an NSRL RDS database or hash list to the allowlist", true, false)
         .add_arg("n", "numerate", "Returns the number of hashes currently in DB", false, false)
+        .add_arg("dbi", "db-info", "Prints the schema version and tables of the database", false, false)
+        .add_arg("w", "why", "Prints which providers and files a hash was inserted from", true, false)
         .add_arg("s", "set-time", "Creates the timestamp in output folder", false, false)
         .add_arg("dd", "de-dup", "Removes duplicates from table", false, false)
         // processing arguments
         .add_arg("t", "tempdir", "Sets the temporary directory; Defaults to ./tmp", true, true)
         .add_arg("d", "database", "Sets the database name; Defaults to hashes_db", true, true)
-        .add_arg("mt", "max-threads", "Sets the max download threads; Defaults to 20", true, true)
+        .add_arg("ad", "allowlist-database", "Sets the allowlist database name; Defaults to allowlist_db", true, true)
+        .add_arg("mt", "max-threads", "Sets the max number of parallel downloads; Defaults to 20", true, true)
         .add_arg("mr", "max-retries", "Sets the max download retries; Defaults to 5", true, true)
+        .add_arg("bo", "backoff", "Sets the delay before the first retry in milliseconds, doubled for each further one; Defaults to 1000", true, true)
+        .add_arg("rps", "max-rps", "Sets the max number of requests per second across all downloads; Defaults to unlimited", true, true)
+        .add_arg("px", "proxy", "Sets the http(s) proxy for all requests", true, true)
+        .add_arg("ca", "ca-bundle", "Sets a pem file with additional certificate authorities to trust", true, true)
+        .add_arg("ua", "user-agent", "Sets the User-Agent sent with requests; Defaults to signature-builder/<version>", true, true)
+        .add_arg("cto", "connect-timeout", "Sets the connect timeout in seconds; Defaults to 10", true, true)
+        .add_arg("rto", "read-timeout", "Sets how many seconds a request may wait for data; Defaults to 30", true, true)
         .add_arg("mc", "max-combines", "Sets how many files can be combined for inserting; Defaults to 8", true, true)
+        .add_arg("ht", "hash-type", "Only inserts hashes of the given type, md5, sha1 or sha256; Defaults to all of them", true, true)
+        .add_arg("rj", "rejects", "Sets the file lines holding no valid hash are reported in; Defaults to rejects.txt", true, true)
         .add_arg("tb", "table", "Sets the tablename; Defaults to hashes", true, true)
+        .add_arg("pr", "providers", "Comma separated list of providers to fetch from; Defaults to virusshare", true, true)
+        .add_arg("mbs", "mb-source", "Sets the MalwareBazaar export to full, recent, a url or a local file; Defaults to full", true, true)
+        .add_arg("tfs", "tf-source", "Sets the ThreatFox export to full, recent, a url or a local file; Defaults to full", true, true)
+        .add_arg("ls", "local-source", "Sets the folder or file:// url the local provider mirrors VirusShare from", true, true)
+        .add_arg("vu", "vs-url", "Sets the url of the folder containing the VirusShare files; Defaults to https://virusshare.com/hashfiles/", true, true)
+        .add_arg("vp", "vs-pattern", "Sets the name of the VirusShare files, {:0>5} is replaced by the file id; Defaults to VirusShare_{:0>5}.md5", true, true)
+        .add_arg("mfi", "max-file-id", "Sets the id of the last VirusShare file instead of probing for it", true, true)
         // output options
         .add_arg("o", "output", "Sets the output folder; Defaults to ./hashes", true, true)
-        .add_arg("l", "length", "The number of lines in output files; Defaults to 1_000_000", true, true);
+        .add_arg("l", "length", "The number of lines in output files; Defaults to 1_000_000", true, true)
+        .add_arg("yo", "yara-output", "Sets the output folder for YARA rules; Defaults to ./yara", true, true)
+        .add_arg("rpf", "rules-per-file", "The number of rules in YARA output files; Defaults to 10_000", true, true);
 
     // parse arguments
     let _ = parser.parse().is_err_and(|err| {
@@ -88,6 +145,12 @@ fn main() -> std::io::Result<()> {
         .unwrap_or(DATABASE.to_string());
     debug!("Set database to {database}");
 
+    let allowlist_database = parser
+        .get_parsed_argument_long("allowlist-database")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(ALLOWLIST_DATABASE.to_string());
+    debug!("Set allowlist_database to {allowlist_database}");
+
     let max_threads = parser
         .get_parsed_argument_long("max-threads")
         .and_then(|parsed_argument| {
@@ -114,6 +177,97 @@ fn main() -> std::io::Result<()> {
         .unwrap_or(MAX_RETRIES);
     debug!("Set max_retries to {max_retries}");
 
+    let backoff = parser
+        .get_parsed_argument_long("backoff")
+        .and_then(|parsed_argument| {
+            parsed_argument.value.map(|value| {
+                value.parse::<u64>().unwrap_or_else(|err| {
+                    error!("Failed to parse {value} for backoff to u64: {err}");
+                    exit(-1)
+                })
+            })
+        })
+        .unwrap_or(BACKOFF);
+    debug!("Set backoff to {backoff}");
+
+    let max_rps = parser
+        .get_parsed_argument_long("max-rps")
+        .and_then(|parsed_argument| {
+            parsed_argument.value.map(|value| {
+                value.parse::<f64>().unwrap_or_else(|err| {
+                    error!("Failed to parse {value} for max-rps to f64: {err}");
+                    exit(-1)
+                })
+            })
+        })
+        .inspect(|max_rps| {
+            if max_rps.is_nan() || *max_rps < MIN_RPS {
+                error!("max-rps must be at least {MIN_RPS}");
+                exit(-1)
+            }
+        });
+    debug!("Set max_rps to {max_rps:?}");
+
+    let proxy = parser
+        .get_parsed_argument_long("proxy")
+        .and_then(|parsed_argument| parsed_argument.value);
+    debug!("Set proxy to {proxy:?}");
+
+    let ca_bundle = parser
+        .get_parsed_argument_long("ca-bundle")
+        .and_then(|parsed_argument| parsed_argument.value);
+    debug!("Set ca_bundle to {ca_bundle:?}");
+
+    let user_agent = parser
+        .get_parsed_argument_long("user-agent")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(USER_AGENT.to_owned());
+    debug!("Set user_agent to {user_agent}");
+
+    let connect_timeout = parser
+        .get_parsed_argument_long("connect-timeout")
+        .and_then(|parsed_argument| {
+            parsed_argument.value.map(|value| {
+                value.parse::<u64>().unwrap_or_else(|err| {
+                    error!("Failed to parse {value} for connect-timeout to u64: {err}");
+                    exit(-1)
+                })
+            })
+        })
+        .unwrap_or(CONNECT_TIMEOUT);
+    debug!("Set connect_timeout to {connect_timeout}");
+
+    let read_timeout = parser
+        .get_parsed_argument_long("read-timeout")
+        .and_then(|parsed_argument| {
+            parsed_argument.value.map(|value| {
+                value.parse::<u64>().unwrap_or_else(|err| {
+                    error!("Failed to parse {value} for read-timeout to u64: {err}");
+                    exit(-1)
+                })
+            })
+        })
+        .unwrap_or(READ_TIMEOUT);
+    debug!("Set read_timeout to {read_timeout}");
+
+    // one client for all downloads, so connections get reused
+    let http = HttpContext::new(
+        &ClientOptions {
+            proxy,
+            ca_bundle,
+            user_agent,
+            connect_timeout: Duration::from_secs(connect_timeout),
+            read_timeout: Duration::from_secs(read_timeout),
+            backoff: Duration::from_millis(backoff),
+            max_rps,
+        },
+        max_retries,
+    )
+    .unwrap_or_else(|err| {
+        error!("Failed to set up http client: {err}");
+        exit(-1)
+    });
+
     let max_combines = parser
         .get_parsed_argument_long("max-combines")
         .and_then(|parsed_argument| {
@@ -133,6 +287,112 @@ fn main() -> std::io::Result<()> {
         .unwrap_or(TABLE_NAME.to_owned());
     debug!("Set table_name to {table_name}");
 
+    let hash_type = parser
+        .get_parsed_argument_long("hash-type")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .map(
+            |value| match HASH_TYPES.iter().any(|(hash_type, _)| *hash_type == value) {
+                true => value,
+                false => {
+                    error!("Unknown hash type {value}");
+                    exit(-1)
+                }
+            },
+        );
+    debug!("Set hash_type to {hash_type:?}");
+
+    let rejects_file = parser
+        .get_parsed_argument_long("rejects")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(REJECTS_FILE.to_owned());
+    debug!("Set rejects_file to {rejects_file}");
+
+    let strict = parser.get_parsed_argument_long("strict").is_some();
+    debug!("Set strict to {strict}");
+
+    let ingest_options = IngestOptions {
+        hash_type,
+        rejects_file,
+        strict,
+    };
+
+    let malwarebazaar_source = parser
+        .get_parsed_argument_long("mb-source")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(MALWAREBAZAAR_SOURCE.to_owned());
+    debug!("Set malwarebazaar_source to {malwarebazaar_source}");
+
+    let threatfox_source = parser
+        .get_parsed_argument_long("tf-source")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(THREATFOX_SOURCE.to_owned());
+    debug!("Set threatfox_source to {threatfox_source}");
+
+    let local_source = parser
+        .get_parsed_argument_long("local-source")
+        .and_then(|parsed_argument| parsed_argument.value);
+    debug!("Set local_source to {local_source:?}");
+
+    let virusshare_url = parser
+        .get_parsed_argument_long("vs-url")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(VIRUSSHARE_URL.to_owned());
+    debug!("Set virusshare_url to {virusshare_url}");
+
+    let virusshare_pattern = parser
+        .get_parsed_argument_long("vs-pattern")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(VIRUSSHARE_PATTERN.to_owned());
+    // fail early instead of on the first request
+    let _ = format_pattern(&virusshare_pattern, 0).is_err_and(|err| {
+        error!("{err}");
+        exit(-1)
+    });
+    debug!("Set virusshare_pattern to {virusshare_pattern}");
+
+    let max_file_id = parser
+        .get_parsed_argument_long("max-file-id")
+        .and_then(|parsed_argument| {
+            parsed_argument.value.map(|value| {
+                value.parse::<usize>().unwrap_or_else(|err| {
+                    error!("Failed to parse {value} for max-file-id to usize: {err}");
+                    exit(-1)
+                })
+            })
+        });
+    debug!("Set max_file_id to {max_file_id:?}");
+
+    let provider_options = ProviderOptions {
+        malwarebazaar_source,
+        threatfox_source,
+        local_source,
+        virusshare_url,
+        virusshare_pattern,
+        max_file_id,
+    };
+    let providers = get_providers(
+        &parser
+            .get_parsed_argument_long("providers")
+            .and_then(|parsed_argument| parsed_argument.value)
+            .unwrap_or(PROVIDERS.to_owned())
+            .split(',')
+            .map(|name| name.trim().to_owned())
+            .collect::<Vec<String>>(),
+        &provider_options,
+    )
+    .unwrap_or_else(|err| {
+        error!("{err}");
+        exit(-1)
+    });
+    debug!(
+        "Set providers to {}",
+        providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect::<Vec<&str>>()
+            .join(",")
+    );
+
     let output_dir = parser
         .get_parsed_argument_long("output")
         .and_then(|parsed_argument| parsed_argument.value)
@@ -152,6 +412,36 @@ fn main() -> std::io::Result<()> {
         .unwrap_or(FILE_SIZE);
     debug!("Set file_size to {file_size}");
 
+    let yara_output_dir = parser
+        .get_parsed_argument_long("yara-output")
+        .and_then(|parsed_argument| parsed_argument.value)
+        .unwrap_or(YARA_OUTPUT_DIR.to_owned());
+    debug!("Set yara_output_dir to {yara_output_dir}");
+
+    let rules_per_file = parser
+        .get_parsed_argument_long("rules-per-file")
+        .and_then(|parsed_argument| {
+            parsed_argument.value.map(|value| {
+                value.parse::<usize>().unwrap_or_else(|err| {
+                    error!("Failed to parse {value} for rules-per-file to usize: {err}");
+                    exit(-1)
+                })
+            })
+        })
+        .unwrap_or(RULES_PER_FILE);
+    debug!("Set rules_per_file to {rules_per_file}");
+
+    let with_names = parser.get_parsed_argument_long("with-names").is_some();
+    debug!("Set with_names to {with_names}");
+
+    let split_hash_types = parser
+        .get_parsed_argument_long("split-hash-types")
+        .is_some();
+    debug!("Set split_hash_types to {split_hash_types}");
+
+    let refetch = parser.get_parsed_argument_long("refetch").is_some();
+    debug!("Set refetch to {refetch}");
+
     let start_time = std::time::Instant::now();
 
     // cleanup
@@ -173,17 +463,29 @@ fn main() -> std::io::Result<()> {
             .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
     }
 
+    // the rejects report only covers the inserts of this run
+    if ["insert", "insert-file", "update", "patch", "allowlist"]
+        .iter()
+        .any(|action| parser.get_parsed_argument_long(action).is_some())
+    {
+        let _ = fs::remove_file(&ingest_options.rejects_file);
+    }
+
     let parsed_arguments = parser.get_parsed_arguments();
     for parsed_argument in parsed_arguments {
         match parsed_argument {
             _ if parsed_argument.long_matches("fetch") => {
-                download_all(tmp_dir_arc.clone(), max_threads, max_retries)?
+                fetch_all(&providers, tmp_dir_arc.clone(), max_threads, &http, refetch)?
+            }
+            _ if parsed_argument.long_matches("retry-failed") => {
+                retry_failed(&provider_options, tmp_dir_arc.clone(), max_threads, &http)?
             }
             _ if parsed_argument.long_matches("insert") => insert_files(
                 tmp_dir.clone(),
                 max_combines,
                 database.clone(),
                 table_name.clone(),
+                &ingest_options,
             )?,
             _ if parsed_argument.long_matches("insert-file") => {
                 let file_path = parser
@@ -193,15 +495,21 @@ fn main() -> std::io::Result<()> {
                         error!("Could not get path for insert-file!");
                         exit(-1)
                     });
-                insert_file(file_path, database.clone(), table_name.clone())?;
+                insert_file(
+                    file_path,
+                    database.clone(),
+                    table_name.clone(),
+                    &ingest_options,
+                )?;
             }
             _ if parsed_argument.long_matches("update") => {
-                download_all(tmp_dir_arc.clone(), max_threads, max_retries)?;
+                fetch_all(&providers, tmp_dir_arc.clone(), max_threads, &http, refetch)?;
                 insert_files(
                     tmp_dir.clone(),
                     max_combines,
                     database.clone(),
                     table_name.clone(),
+                    &ingest_options,
                 )?;
             }
             _ if parsed_argument.long_matches("export") => {
@@ -210,9 +518,21 @@ fn main() -> std::io::Result<()> {
                     file_size,
                     database.clone(),
                     table_name.clone(),
+                    allowlist_database.clone(),
+                    with_names,
+                    split_hash_types,
                 )?;
                 set_timestamp(output_dir.clone())?;
             }
+            _ if parsed_argument.long_matches("export-yara") => {
+                write_yara(
+                    yara_output_dir.clone(),
+                    rules_per_file,
+                    database.clone(),
+                    table_name.clone(),
+                    allowlist_database.clone(),
+                )?;
+            }
             _ if parsed_argument.long_matches("set-time") => {
                 set_timestamp(output_dir.clone())?;
             }
@@ -236,21 +556,147 @@ fn main() -> std::io::Result<()> {
                         error!("Could not get path for path!");
                         exit(-1)
                     });
-                patch(database.clone(), table_name.clone(), file_path)?;
+                patch(
+                    database.clone(),
+                    table_name.clone(),
+                    file_path,
+                    &ingest_options,
+                )?;
+            }
+            _ if parsed_argument.long_matches("allowlist") => {
+                let file_path = parser
+                    .get_parsed_argument_long("allowlist")
+                    .and_then(|parsed_argument| parsed_argument.value)
+                    .unwrap_or_else(|| {
+                        error!("Could not get path for allowlist!");
+                        exit(-1)
+                    });
+                load_allowlist(file_path, allowlist_database.clone(), &ingest_options)?;
             }
             _ if parsed_argument.long_matches("numerate") => {
                 let database_connection = create_pool(database.clone(), table_name.clone())
                     .map_err(|err| {
                         std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
                     })?;
+                attach_allowlist(&database_connection, allowlist_database.clone()).map_err(
+                    |err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+                )?;
                 let count =
                     get_hash_count(&database_connection, table_name.clone()).map_err(|err| {
                         std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
                     })?;
+                let allowed =
+                    get_allowed_count(&database_connection, table_name.clone()).map_err(|err| {
+                        std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                    })?;
+                let hash_type_counts =
+                    get_hash_type_counts(&database_connection, table_name.clone()).map_err(
+                        |err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+                    )?;
                 database_connection.close().map_err(|err| {
                     std::io::Error::new(std::io::ErrorKind::Other, err.1.to_string())
                 })?;
                 info!("There are currently {count} hashes in DB");
+                info!("{allowed} of them are on the allowlist and suppressed on export");
+                for (hash_type, count) in hash_type_counts {
+                    info!(
+                        "{count} of them are {}",
+                        hash_type.unwrap_or("of unknown type".to_owned())
+                    );
+                }
+            }
+            _ if parsed_argument.long_matches("db-info") => {
+                if !Path::new(&database).exists() {
+                    info!("{database} does not exist yet");
+                    continue;
+                }
+                // opened without create_pool, so the schema is reported as it is before migrating
+                let database_connection =
+                    rusqlite::Connection::open(database.clone()).map_err(|err| {
+                        std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                    })?;
+                let version = get_schema_version(&database_connection).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                })?;
+                info!(
+                    "{database} has schema version {version}, the latest is {}",
+                    latest_schema_version()
+                );
+                (version < latest_schema_version())
+                    .then(|| info!("It will be migrated the next time it is opened"));
+                let tables = get_hash_tables(&database_connection).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                })?;
+                for table in tables {
+                    let count =
+                        get_hash_count(&database_connection, table.clone()).map_err(|err| {
+                            std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                        })?;
+                    info!("Table {table} holds {count} hashes");
+                }
+            }
+            _ if parsed_argument.long_matches("why") => {
+                let hash = parser
+                    .get_parsed_argument_long("why")
+                    .and_then(|parsed_argument| parsed_argument.value)
+                    .unwrap_or_else(|| {
+                        error!("Could not get hash for why!");
+                        exit(-1)
+                    });
+                // hashes are stored as raw bytes, so only valid ones can be looked up
+                let hash = normalize_hash(clean_line(&hash), None).unwrap_or_else(|reason| {
+                    error!("{hash} is no valid hash: {reason}");
+                    exit(-1)
+                });
+                let database_connection = create_pool(database.clone(), table_name.clone())
+                    .map_err(|err| {
+                        std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                    })?;
+                attach_allowlist(&database_connection, allowlist_database.clone()).map_err(
+                    |err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+                )?;
+                let occurrences =
+                    get_hash_occurrences(&database_connection, table_name.clone(), &hash).map_err(
+                        |err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+                    )?;
+                let allowed = is_allowed(&database_connection, &hash).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                })?;
+                let provenance = get_provenance(&database_connection, table_name.clone(), &hash)
+                    .map_err(|err| {
+                        std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+                    })?;
+                database_connection.close().map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::Other, err.1.to_string())
+                })?;
+                match occurrences {
+                    0 => info!("{hash} is not in DB"),
+                    _ => info!("{hash} is in DB"),
+                }
+                allowed.then(|| info!("{hash} is on the allowlist and suppressed on export"));
+                match provenance.is_empty() {
+                    true => info!("No inserts of {hash} were recorded"),
+                    false => info!("{hash} was inserted from:"),
+                }
+                for entry in provenance {
+                    // files inserted again, like on every update, are listed once
+                    let ingested_at = match entry.first_ingested_at == entry.last_ingested_at {
+                        true => entry.first_ingested_at,
+                        false => {
+                            format!("{} to {}", entry.first_ingested_at, entry.last_ingested_at)
+                        }
+                    };
+                    info!(
+                        "{ingested_at} {} {}{}",
+                        entry.source.provider,
+                        entry.source.file,
+                        entry
+                            .source
+                            .url
+                            .map(|url| format!(" ({url})"))
+                            .unwrap_or_default()
+                    );
+                }
             }
             _ => {}
         }
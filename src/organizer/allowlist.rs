@@ -0,0 +1,194 @@
+use std::{
+    fs::File,
+    io::{BufRead, Read},
+    path::Path,
+};
+
+use log::info;
+
+use crate::{
+    downloader::download_commons::split_csv_line,
+    organizer::{
+        compression::read_members,
+        database::{create_allowlist, import_nsrl, insert_allowed_hashes},
+        normalize::{clean_line, normalize_hash, IngestOptions, Rejects},
+    },
+};
+
+/// loads known-good hashes from an NSRL RDS SQLite database or a list of hashes into the allowlist database. lines of a list
+/// holding no valid hash are reported as rejects, which fails before anything is added in strict mode
+pub fn load_allowlist(
+    file_path: String,
+    allowlist_database: String,
+    options: &IngestOptions,
+) -> std::io::Result<()> {
+    let start_time = std::time::Instant::now();
+
+    let mut connection = create_allowlist(allowlist_database)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+
+    let added = match is_sqlite(Path::new(&file_path))? {
+        true => {
+            info!("Importing NSRL RDS database {file_path} into allowlist...");
+            import_nsrl(&connection, file_path.clone())
+        }
+        false => {
+            let mut hashes = Vec::new();
+            let mut rejects = Rejects::default();
+            read_members(Path::new(&file_path), |reader| {
+                for (index, line) in reader.lines().enumerate() {
+                    let line = line?;
+                    match hashes_in_line(&line, index == 0, options.hash_type.as_deref()) {
+                        Ok(found) => hashes.extend(found),
+                        Err(reason) => rejects.push(&file_path, index + 1, reason, &line),
+                    }
+                }
+                Ok(())
+            })?;
+            rejects.report(Path::new(&options.rejects_file), options.strict)?;
+            info!(
+                "Adding file {file_path} containing {} hashes to allowlist...",
+                hashes.len()
+            );
+            insert_allowed_hashes(&mut connection, &hashes)
+        }
+    }
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+
+    info!(
+        "Added {added} new hashes to allowlist in {}s",
+        std::time::Instant::now()
+            .duration_since(start_time)
+            .as_secs_f32()
+    );
+    Ok(())
+}
+
+/// checks if path is a SQLite database by its header
+fn is_sqlite(path: &Path) -> std::io::Result<bool> {
+    let mut magic = Vec::with_capacity(16);
+    File::open(path)?.take(16).read_to_end(&mut magic)?;
+    Ok(magic == b"SQLite format 3\0")
+}
+
+/// returns the hashes of a line of a plain hash list or of a csv file like NSRLFile.txt, whose fields holding a valid hash are
+/// kept. blank lines, comments and the header of a csv file are skipped. returns why the line holds no valid hash otherwise
+fn hashes_in_line(
+    line: &str,
+    first_line: bool,
+    hash_type: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let line = clean_line(line);
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(Vec::new());
+    }
+    if !line.contains(',') {
+        return normalize_hash(line, hash_type).map(|hash| vec![hash]);
+    }
+    let hashes = split_csv_line(line)
+        .iter()
+        .filter_map(|field| normalize_hash(field, hash_type).ok())
+        .collect::<Vec<String>>();
+    match hashes.is_empty() {
+        true if first_line => Ok(hashes),
+        true => Err(match hash_type {
+            Some(hash_type) => format!("no field holds a {hash_type} hash"),
+            None => "no field holds a md5, sha1 or sha256 hash".to_owned(),
+        }),
+        false => Ok(hashes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    static MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+    static SHA1: &str = "3395856ce81f2b7382dee72602f798b642f14140";
+
+    #[test]
+    fn hashes_in_line_reads_plain_lists_and_csv() {
+        assert_eq!(
+            hashes_in_line(&format!("\u{feff}{}\r", MD5.to_uppercase()), true, None),
+            Ok(vec![MD5.to_owned()])
+        );
+        assert_eq!(hashes_in_line("  ", false, None), Ok(Vec::new()));
+        assert_eq!(hashes_in_line("# comment", false, None), Ok(Vec::new()));
+        // a line of NSRLFile.txt
+        let line = format!(
+            r#""{}","{}","2AB19E38","eicar.com",68,1,"WIN",""#,
+            SHA1.to_uppercase(),
+            MD5.to_uppercase()
+        );
+        assert_eq!(
+            hashes_in_line(&line, false, None),
+            Ok(vec![SHA1.to_owned(), MD5.to_owned()])
+        );
+        assert_eq!(
+            hashes_in_line(&line, false, Some("md5")),
+            Ok(vec![MD5.to_owned()])
+        );
+        // the header of NSRLFile.txt
+        let header = r#""SHA-1","MD5","CRC32","FileName","FileSize","ProductCode","OpSystemCode","SpecialCode""#;
+        assert_eq!(hashes_in_line(header, true, None), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn hashes_in_line_rejects_lines_without_hash() {
+        assert_eq!(
+            hashes_in_line("<html>", false, None),
+            Err("not hexadecimal".to_owned())
+        );
+        assert_eq!(
+            hashes_in_line(MD5, false, Some("sha1")),
+            Err("length 32 does not fit sha1".to_owned())
+        );
+        assert_eq!(
+            hashes_in_line("a,b,c", false, None),
+            Err("no field holds a md5, sha1 or sha256 hash".to_owned())
+        );
+        assert!(hashes_in_line(&format!("{MD5},x"), false, Some("sha1")).is_err());
+    }
+
+    #[test]
+    fn strict_load_allowlist_adds_nothing_if_a_line_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        fs::write(path("allowlist.txt"), format!("{MD5}\n<html>\n{SHA1}\n")).unwrap();
+        let options = IngestOptions {
+            hash_type: None,
+            rejects_file: path("rejects.txt"),
+            strict: true,
+        };
+
+        let err =
+            load_allowlist(path("allowlist.txt"), path("allowlist_db"), &options).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let report = fs::read_to_string(path("rejects.txt")).unwrap();
+        assert!(report.starts_with(&format!("{}:2\t", path("allowlist.txt"))));
+        let connection = create_allowlist(path("allowlist_db")).unwrap();
+        assert_eq!(
+            connection
+                .query_row("SELECT COUNT(*) FROM allowlist", [], |row| row
+                    .get::<_, u64>(0))
+                .unwrap(),
+            0
+        );
+
+        // without strict the valid hashes are added
+        let options = IngestOptions {
+            strict: false,
+            ..options
+        };
+        load_allowlist(path("allowlist.txt"), path("allowlist_db"), &options).unwrap();
+        assert_eq!(
+            connection
+                .query_row("SELECT COUNT(*) FROM allowlist", [], |row| row
+                    .get::<_, u64>(0))
+                .unwrap(),
+            2
+        );
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use flate2::read::GzDecoder;
+use log::debug;
+use tar::Archive;
+
+use crate::organizer::{
+    compression::read_members,
+    database::Signature,
+    normalize::{clean_line, normalize_hash, Rejects},
+};
+
+/// extensions of ClamAV hash databases; md5 in hdb, sha1 or sha256 in hsb and the unofficial hdu and hsu variants
+static HASH_DATABASES: [&str; 4] = ["hdb", "hsb", "hdu", "hsu"];
+/// extensions of ClamAV database containers; a cvd holds a gzipped tar archive, a cld a plain one
+static CONTAINERS: [&str; 2] = ["cvd", "cld"];
+/// length of the header in front of the tar archive of a container
+static HEADER_LENGTH: usize = 512;
+
+/// returns the extension of path if it is one of extensions
+fn matching_extension<'a>(path: &Path, extensions: &[&'a str]) -> Option<&'a str> {
+    let extension = path.extension()?.to_str()?;
+    extensions
+        .iter()
+        .find(|candidate| **candidate == extension)
+        .copied()
+}
+
+/// checks by its extension if path is a ClamAV hash database or a container of them
+pub fn is_clamav_file(path: &Path) -> bool {
+    matching_extension(path, &HASH_DATABASES).is_some()
+        || matching_extension(path, &CONTAINERS).is_some()
+}
+
+/// reads the signatures of a hash database or of all hash databases inside a container. invalid lines are added to rejects,
+/// hashes not fitting hash_type as well if it is given
+pub fn read_signatures(
+    path: &Path,
+    hash_type: Option<&str>,
+    rejects: &mut Rejects,
+) -> std::io::Result<Vec<Signature>> {
+    let mut signatures = Vec::new();
+    let mut database = HashDatabase {
+        hash_type,
+        signatures: &mut signatures,
+        rejects,
+    };
+    match matching_extension(path, &CONTAINERS) {
+        Some(_) => read_container(path, &mut database)?,
+        None => read_members(path, |reader| {
+            database.parse(reader, &path.display().to_string())
+        })?,
+    }
+    debug!(
+        "Read {} signatures from {}",
+        signatures.len(),
+        path.display()
+    );
+    Ok(signatures)
+}
+
+/// unpacks a container and parses the hash databases inside it
+fn read_container(path: &Path, database: &mut HashDatabase) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut header = vec![0; HEADER_LENGTH];
+    file.read_exact(&mut header)?;
+    if !header.starts_with(b"ClamAV-VDB:") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a ClamAV database", path.display()),
+        ));
+    }
+    // the header is padded with spaces
+    debug!(
+        "Unpacking {} ({})",
+        path.display(),
+        String::from_utf8_lossy(&header).trim_end()
+    );
+
+    let mut reader = BufReader::new(file);
+    let reader: Box<dyn Read> = match reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        true => Box::new(GzDecoder::new(reader)),
+        false => Box::new(reader),
+    };
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        match matching_extension(&entry_path, &HASH_DATABASES) {
+            Some(_) => database.parse(
+                &mut BufReader::new(entry),
+                &format!("{}/{}", path.display(), entry_path.display()),
+            )?,
+            None => debug!("Skipping {} as it holds no hashes", entry_path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// collects the signatures of the hash databases read and the lines which are no valid signature
+struct HashDatabase<'a> {
+    hash_type: Option<&'a str>,
+    signatures: &'a mut Vec<Signature>,
+    rejects: &'a mut Rejects,
+}
+
+impl HashDatabase<'_> {
+    /// parses the lines of a hash database, adding invalid ones to the rejects
+    fn parse(&mut self, reader: &mut dyn BufRead, source: &str) -> std::io::Result<()> {
+        for (index, line) in reader.split(b'\n').enumerate() {
+            let line = String::from_utf8_lossy(&line?).into_owned();
+            let line = clean_line(&line);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_signature(line, self.hash_type) {
+                Ok(signature) => self.signatures.push(signature),
+                Err(reason) => self.rejects.push(source, index + 1, reason, line),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// parses a hash:size:name line, which may be followed by the minimum and maximum engine level. size is * if the signature matches
+/// files of any size. returns why the line is no valid signature otherwise
+fn parse_signature(line: &str, hash_type: Option<&str>) -> Result<Signature, String> {
+    let mut fields = line.split(':');
+    let hash = normalize_hash(fields.next().unwrap_or_default(), hash_type)?;
+    let file_size = match fields.next() {
+        Some("*") => None,
+        Some(file_size) => Some(
+            file_size
+                .parse::<u64>()
+                .map_err(|_| format!("invalid file size {file_size}"))?,
+        ),
+        None => return Err("missing file size".to_owned()),
+    };
+    let name = fields
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or("missing signature name".to_owned())?;
+    Ok(Signature {
+        hash,
+        file_size,
+        name: Some(name.to_owned()),
+        confidence: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use flate2::{write::GzEncoder, Compression};
+    use tar::{Builder, Header};
+
+    use super::*;
+
+    static MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+    static SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+
+    /// builds a container holding files, with a gzipped archive like a cvd or a plain one like a cld
+    fn container(files: &[(&str, &str)], gzipped: bool) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (name, content) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        let mut data = format!(
+            "{:<512}",
+            "ClamAV-VDB:17 Oct 2026 04-00 +0000:27000:100:90:sig:test:1700000000"
+        )
+        .into_bytes();
+        match gzipped {
+            true => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&archive).unwrap();
+                data.extend(encoder.finish().unwrap());
+            }
+            false => data.extend(archive),
+        }
+        data
+    }
+
+    /// writes the rejects into a fresh report and returns its lines
+    fn reported(rejects: &Rejects, dir: &Path) -> Vec<String> {
+        let path = dir.join("rejects.txt");
+        fs::remove_file(&path).ok();
+        rejects.report(&path, false).unwrap();
+        fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn parse_signature_reads_hash_size_and_name() {
+        assert_eq!(
+            parse_signature(
+                &format!("{}:68:Eicar-Test-Signature", MD5.to_uppercase()),
+                None
+            ),
+            Ok(Signature {
+                hash: MD5.to_owned(),
+                file_size: Some(68),
+                name: Some("Eicar-Test-Signature".to_owned()),
+                confidence: None,
+            })
+        );
+        // any file size and engine levels
+        let signature = parse_signature(&format!("{SHA256}:*:Win.Test:73:255"), None).unwrap();
+        assert_eq!(signature.hash, SHA256);
+        assert_eq!(signature.file_size, None);
+        assert_eq!(signature.name.as_deref(), Some("Win.Test"));
+    }
+
+    #[test]
+    fn parse_signature_gives_reasons() {
+        let cases = [
+            (format!("{MD5}:68"), "missing signature name"),
+            (format!("{MD5}:68:"), "missing signature name"),
+            (MD5.to_owned(), "missing file size"),
+            (format!("{MD5}:large:Test"), "invalid file size large"),
+            ("not-a-hash:68:Test".to_owned(), "not hexadecimal"),
+            (":68:Test".to_owned(), "empty hash"),
+        ];
+        for (line, reason) in cases {
+            assert_eq!(
+                parse_signature(&line, None),
+                Err(reason.to_owned()),
+                "{line}"
+            );
+        }
+        assert_eq!(
+            parse_signature(&format!("{MD5}:68:Test"), Some("sha256")),
+            Err("length 32 does not fit sha256".to_owned())
+        );
+    }
+
+    #[test]
+    fn read_signatures_of_hash_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.hdb");
+        fs::write(
+            &path,
+            format!("# comment\n\n{MD5}:68:Eicar\r\n{MD5}:68\n{SHA256}:*:Sha256\n"),
+        )
+        .unwrap();
+        let mut rejects = Rejects::default();
+        let signatures = read_signatures(&path, None, &mut rejects).unwrap();
+        assert_eq!(
+            signatures
+                .iter()
+                .map(|signature| signature.name.as_deref().unwrap())
+                .collect::<Vec<&str>>(),
+            ["Eicar", "Sha256"]
+        );
+        assert_eq!(
+            reported(&rejects, dir.path()),
+            [format!(
+                "{}:4\tmissing signature name\t{MD5}:68",
+                path.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn read_container_unpacks_gzipped_and_plain_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let (hdb, hsb) = (
+            format!("{MD5}:68:Eicar\ninvalid\n"),
+            format!("{SHA256}:*:Sha256\n"),
+        );
+        let files = [
+            ("daily.info", "DAILY:27000\n"),
+            ("daily.hdb", hdb.as_str()),
+            ("daily.hsb", hsb.as_str()),
+        ];
+        for (name, gzipped) in [("daily.cvd", true), ("daily.cld", false)] {
+            let path = dir.path().join(name);
+            fs::write(&path, container(&files, gzipped)).unwrap();
+            assert!(is_clamav_file(&path));
+            let mut rejects = Rejects::default();
+            let signatures = read_signatures(&path, None, &mut rejects).unwrap();
+            assert_eq!(
+                signatures
+                    .iter()
+                    .map(|signature| signature.hash.as_str())
+                    .collect::<Vec<&str>>(),
+                [MD5, SHA256],
+                "{name}"
+            );
+            assert_eq!(rejects.len(), 1, "{name}");
+            assert_eq!(
+                reported(&rejects, dir.path()),
+                [format!(
+                    "{}/daily.hdb:2\tnot hexadecimal\tinvalid",
+                    path.display()
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn read_container_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("daily.cvd");
+        fs::write(&path, vec![b' '; HEADER_LENGTH * 2]).unwrap();
+        let err = read_signatures(&path, None, &mut Rejects::default()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // too short for a header
+        fs::write(&path, b"ClamAV-VDB:").unwrap();
+        assert!(read_signatures(&path, None, &mut Rejects::default()).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek},
+    path::Path,
+};
+
+use flate2::read::MultiGzDecoder;
+use log::debug;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+/// formats input files may be compressed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    Plain,
+    Gzip,
+    Zip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// detects the compression of file by its magic bytes, falling back to the extension of path
+    fn detect(path: &Path, file: &mut File) -> std::io::Result<Self> {
+        let mut magic = Vec::with_capacity(6);
+        file.by_ref().take(6).read_to_end(&mut magic)?;
+        file.rewind()?;
+        Ok(match magic.as_slice() {
+            [0x1f, 0x8b, ..] => Self::Gzip,
+            [b'P', b'K', 0x03, 0x04, ..] => Self::Zip,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Self::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zstd,
+            _ => match path.extension().and_then(|extension| extension.to_str()) {
+                Some("gz") => Self::Gzip,
+                Some("zip") => Self::Zip,
+                Some("xz") => Self::Xz,
+                Some("zst") => Self::Zstd,
+                _ => Self::Plain,
+            },
+        })
+    }
+}
+
+/// opens path and calls read with the decompressed content of each member. plain and single stream files have one member,
+/// zip archives one for every file they contain
+pub fn read_members(
+    path: &Path,
+    mut read: impl FnMut(&mut dyn BufRead) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let compression = Compression::detect(path, &mut file)?;
+    debug!("Reading {} as {compression:?}", path.display());
+    match compression {
+        Compression::Plain => read(&mut BufReader::new(file)),
+        Compression::Gzip => read(&mut BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Xz => read(&mut BufReader::new(XzDecoder::new_multi_decoder(file))),
+        Compression::Zstd => read(&mut BufReader::new(zstd::Decoder::new(file)?)),
+        Compression::Zip => {
+            let mut archive = ZipArchive::new(file)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+            for index in 0..archive.len() {
+                let member = archive
+                    .by_index(index)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+                if member.is_file() {
+                    read(&mut BufReader::new(member))?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use flate2::write::GzEncoder;
+    use xz2::write::XzEncoder;
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    static CONTENT: &str = "00000000000000000000000000000001\n00000000000000000000000000000002\n";
+
+    /// writes content compressed as compression to name in dir
+    fn write_compressed(
+        dir: &Path,
+        name: &str,
+        compression: Compression,
+        content: &str,
+    ) -> PathBuf {
+        let path = dir.join(name);
+        let file = File::create(&path).unwrap();
+        match compression {
+            Compression::Plain => fs::write(&path, content).unwrap(),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                encoder.write_all(content.as_bytes()).unwrap();
+                encoder.finish().unwrap();
+            }
+            Compression::Xz => {
+                let mut encoder = XzEncoder::new(file, 6);
+                encoder.write_all(content.as_bytes()).unwrap();
+                encoder.finish().unwrap();
+            }
+            Compression::Zstd => zstd::stream::copy_encode(content.as_bytes(), file, 0).unwrap(),
+            Compression::Zip => {
+                let mut writer = ZipWriter::new(file);
+                writer
+                    .start_file("hashes.txt", FileOptions::default())
+                    .unwrap();
+                writer.write_all(content.as_bytes()).unwrap();
+                writer.finish().unwrap();
+            }
+        }
+        path
+    }
+
+    /// returns the content of each member of path
+    fn members(path: &Path) -> Vec<String> {
+        let mut members = Vec::new();
+        read_members(path, |reader| {
+            let mut content = String::new();
+            reader.read_to_string(&mut content)?;
+            members.push(content);
+            Ok(())
+        })
+        .unwrap();
+        members
+    }
+
+    #[test]
+    fn detects_compression_by_magic_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [
+            Compression::Plain,
+            Compression::Gzip,
+            Compression::Zip,
+            Compression::Xz,
+            Compression::Zstd,
+        ] {
+            // no extension, so only the magic bytes tell the format
+            let path = write_compressed(
+                dir.path(),
+                &format!("{compression:?}"),
+                compression,
+                CONTENT,
+            );
+            let mut file = File::open(&path).unwrap();
+            assert_eq!(Compression::detect(&path, &mut file).unwrap(), compression);
+            assert_eq!(members(&path), [CONTENT], "{compression:?}");
+        }
+    }
+
+    #[test]
+    fn magic_bytes_win_over_wrong_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_compressed(dir.path(), "hashes.zip", Compression::Gzip, CONTENT);
+        assert_eq!(members(&path), [CONTENT]);
+        let path = write_compressed(dir.path(), "hashes.gz", Compression::Zstd, CONTENT);
+        assert_eq!(members(&path), [CONTENT]);
+
+        // without magic bytes the extension decides
+        let path = write_compressed(dir.path(), "hashes.xz", Compression::Plain, "");
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(
+            Compression::detect(&path, &mut file).unwrap(),
+            Compression::Xz
+        );
+    }
+
+    #[test]
+    fn reads_every_file_of_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.zip");
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        writer
+            .start_file("first.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"first\n").unwrap();
+        writer
+            .add_directory("folder", FileOptions::default())
+            .unwrap();
+        writer
+            .start_file("folder/second.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"second\n").unwrap();
+        writer
+            .start_file("empty.txt", FileOptions::default())
+            .unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(members(&path), ["first\n", "second\n", ""]);
+    }
+}
@@ -1,31 +1,120 @@
+use std::{fmt::Write, path::Path};
+
 use log::{info, trace};
 use rusqlite::params;
 
-/// creates the database connection pool
+use crate::organizer::migrations::migrate;
+
+/// hash algorithms told apart by the length of their hex digest
+pub static HASH_TYPES: [(&str, usize); 3] = [("md5", 32), ("sha1", 40), ("sha256", 64)];
+
+/// condition leaving out hashes on the attached allowlist, which keeps them as lowercase hex
+static NOT_ALLOWED: &str = "lower(hex(hash)) NOT IN (SELECT hash FROM allowlist.allowlist)";
+
+/// creates the database connection pool, migrating the schema of databases created by older versions
 pub fn create_pool(
     database: String,
     table_name: String,
 ) -> Result<rusqlite::Connection, rusqlite::Error> {
-    let connection = rusqlite::Connection::open(database)?;
+    let mut connection = rusqlite::Connection::open(database)?;
+    migrate(&mut connection)?;
     create_table(&connection, table_name.clone())?;
     Ok(connection)
 }
 
+/// a hash together with what is known about the file it was taken from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub hash: String,
+    /// size of the file in bytes; None if unknown or the signature matches any size
+    pub file_size: Option<u64>,
+    /// name the detection is reported as, e.g. Win.Trojan.Agent-1, or the malware family
+    pub name: Option<String>,
+    /// how sure the provider is that the file is malicious, from 0 to 100
+    pub confidence: Option<u8>,
+}
+
+/// detects the algorithm of a hex hash by its length. None if it is no md5, sha1 or sha256
+pub fn hash_type(hash: &str) -> Option<&'static str> {
+    if !hash.chars().all(|char| char.is_ascii_hexdigit()) {
+        return None;
+    }
+    HASH_TYPES
+        .iter()
+        .find(|(_, length)| *length == hash.len())
+        .map(|(hash_type, _)| *hash_type)
+}
+
+/// converts a hex hash into the raw bytes it is stored as, which take half the space
+pub fn hash_to_blob(hash: &str) -> Result<Vec<u8>, rusqlite::Error> {
+    if !hash.len().is_multiple_of(2) || !hash.is_ascii() {
+        return Err(rusqlite::Error::ToSqlConversionFailure(Box::new(
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{hash} is no hex hash"),
+            ),
+        )));
+    }
+    (0..hash.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hash[index..index + 2], 16))
+        .collect::<Result<Vec<u8>, std::num::ParseIntError>>()
+        .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))
+}
+
+/// converts the raw bytes of a stored hash back into lowercase hex
+pub fn blob_to_hash(blob: &[u8]) -> String {
+    blob.iter()
+        .fold(String::with_capacity(blob.len() * 2), |mut hash, byte| {
+            let _ = write!(hash, "{byte:02x}");
+            hash
+        })
+}
+
 /// creates table in database if not already existent
 pub fn create_table(
     connection: &rusqlite::Connection,
     table_name: String,
 ) -> Result<(), rusqlite::Error> {
+    // tables of databases created by older versions are brought up to this layout by the migrations
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {table_name} (id INTEGER PRIMARY KEY, hash BLOB NOT NULL, file_size INTEGER, name TEXT, confidence INTEGER, first_seen INTEGER, last_seen INTEGER)",
+        ),
+        [],
+    )?;
+    // every hash is stored once
+    connection.execute(
+        &format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {table_name}_hash_unique ON {table_name} (hash)"
+        ),
+        [],
+    )?;
+
+    // every file hashes were inserted from and which hashes it contained. a file inserted again is the same source
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {table_name}_sources (id INTEGER PRIMARY KEY, provider TEXT NOT NULL, file TEXT NOT NULL, url TEXT, first_ingested_at INTEGER NOT NULL, last_ingested_at INTEGER NOT NULL)",
+        ),
+        [],
+    )?;
     connection.execute(
         &format!(
-            "CREATE TABLE IF NOT EXISTS {table_name} (id INTEGER PRIMARY KEY, hash TEXT NOT NULL)",
+            "CREATE UNIQUE INDEX IF NOT EXISTS {table_name}_sources_unique ON {table_name}_sources (provider, file, ifnull(url, ''))",
+        ),
+        [],
+    )?;
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {table_name}_provenance (hash BLOB NOT NULL, source_id INTEGER NOT NULL REFERENCES {table_name}_sources (id), PRIMARY KEY (hash, source_id)) WITHOUT ROWID",
         ),
         [],
     )?;
     Ok(())
 }
 
-/// removes duplicates from the table
+/// removes duplicates from the table. the unique index on hash keeps new ones from being inserted, so this only matters for tables
+/// which could not be migrated
 pub fn remove_duplicates(
     connection: &rusqlite::Connection,
     table_name: String,
@@ -36,7 +125,7 @@ pub fn remove_duplicates(
     Ok(())
 }
 
-/// inserts a vectore of hashes into database
+/// inserts a vectore of hashes into database. hashes already in it are only marked as seen again
 pub fn insert_hashes(
     connection: &mut rusqlite::Connection,
     table_name: String,
@@ -44,18 +133,137 @@ pub fn insert_hashes(
 ) -> Result<(), rusqlite::Error> {
     // we use transactions to speed up large inserts
     let transaction = connection.transaction()?;
-    for hash in hashes {
-        trace!("Inserting {hash}");
-        transaction.execute(
-            &format!("INSERT OR IGNORE INTO {} (hash) VALUES (?1)", table_name),
-            params![hash],
-        )?;
+    {
+        let mut sql = transaction.prepare(&format!(
+            "INSERT INTO {table_name} (hash, first_seen, last_seen) VALUES (?1, unixepoch(), unixepoch())
+            ON CONFLICT (hash) DO UPDATE SET last_seen = excluded.last_seen"
+        ))?;
+        for hash in hashes {
+            trace!("Inserting {hash}");
+            sql.execute(params![hash_to_blob(hash)?])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// inserts a vector of signatures together with their file size, name and confidence into database. signatures already in it
+/// are marked as seen again and get what was unknown about them so far
+pub fn insert_signatures(
+    connection: &mut rusqlite::Connection,
+    table_name: String,
+    signatures: &[Signature],
+) -> Result<(), rusqlite::Error> {
+    // we use transactions to speed up large inserts
+    let transaction = connection.transaction()?;
+    {
+        let mut sql = transaction.prepare(&format!(
+            "INSERT INTO {table_name} (hash, file_size, name, confidence, first_seen, last_seen) VALUES (?1, ?2, ?3, ?4, unixepoch(), unixepoch())
+            ON CONFLICT (hash) DO UPDATE SET last_seen = excluded.last_seen, file_size = COALESCE(file_size, excluded.file_size),
+            name = COALESCE(name, excluded.name), confidence = COALESCE(confidence, excluded.confidence)"
+        ))?;
+        for signature in signatures {
+            trace!("Inserting {signature:?}");
+            sql.execute(params![
+                hash_to_blob(&signature.hash)?,
+                signature.file_size,
+                signature.name,
+                signature.confidence
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// a file hashes were inserted from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    /// name of the provider the file was fetched from, manual for files inserted by hand and patch for patches
+    pub provider: String,
+    pub file: String,
+    /// where the file was downloaded from, if it was fetched
+    pub url: Option<String>,
+}
+
+/// an entry of the history of a hash
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provenance {
+    pub source: Source,
+    /// time of the first insert of the source in UTC, formatted as YYYY-MM-DD HH:MM:SS
+    pub first_ingested_at: String,
+    /// time of the latest insert of the source, formatted like first_ingested_at
+    pub last_ingested_at: String,
+}
+
+/// records that hashes were inserted from source just now. a source inserted before is only marked as inserted again and
+/// only hashes it did not contain before are added to it
+pub fn insert_provenance(
+    connection: &mut rusqlite::Connection,
+    table_name: String,
+    source: &Source,
+    hashes: &[&str],
+) -> Result<(), rusqlite::Error> {
+    // we use transactions to speed up large inserts
+    let transaction = connection.transaction()?;
+    let source_id: i64 = transaction.query_row(
+        &format!(
+            "INSERT INTO {table_name}_sources (provider, file, url, first_ingested_at, last_ingested_at) VALUES (?1, ?2, ?3, unixepoch(), unixepoch())
+            ON CONFLICT (provider, file, ifnull(url, '')) DO UPDATE SET last_ingested_at = excluded.last_ingested_at RETURNING id"
+        ),
+        params![source.provider, source.file, source.url],
+        |row| row.get(0),
+    )?;
+    {
+        let mut sql = transaction.prepare(&format!(
+            "INSERT OR IGNORE INTO {table_name}_provenance (hash, source_id) VALUES (?1, ?2)"
+        ))?;
+        for hash in hashes {
+            sql.execute(params![hash_to_blob(hash)?, source_id])?;
+        }
     }
     transaction.commit()?;
     Ok(())
 }
 
-/// removes a vector of hashes from database
+/// gets every source hash was inserted from, oldest first
+pub fn get_provenance(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    hash: &str,
+) -> Result<Vec<Provenance>, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!(
+        "SELECT sources.provider, sources.file, sources.url, datetime(sources.first_ingested_at, 'unixepoch'), datetime(sources.last_ingested_at, 'unixepoch') FROM {table_name}_provenance AS provenance JOIN {table_name}_sources AS sources ON sources.id = provenance.source_id WHERE provenance.hash = ?1 ORDER BY sources.first_ingested_at, sources.id"
+    ))?;
+    let provenance = sql
+        .query_map(params![hash_to_blob(hash)?], |row| {
+            Ok(Provenance {
+                source: Source {
+                    provider: row.get(0)?,
+                    file: row.get(1)?,
+                    url: row.get(2)?,
+                },
+                first_ingested_at: row.get(3)?,
+                last_ingested_at: row.get(4)?,
+            })
+        })?
+        .collect();
+    provenance
+}
+
+/// gets how often hash is currently in the table
+pub fn get_hash_occurrences(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    hash: &str,
+) -> Result<u64, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!(
+        "SELECT COUNT(*) FROM {table_name} WHERE hash = ?1"
+    ))?;
+    sql.query_row(params![hash_to_blob(hash)?], |row| row.get(0))
+}
+
+/// removes a vector of hashes from database together with their provenance
 pub fn remove_hashes(
     connection: &mut rusqlite::Connection,
     table_name: String,
@@ -63,18 +271,24 @@ pub fn remove_hashes(
 ) -> Result<(), rusqlite::Error> {
     // transactions for faster large removes
     let transaction = connection.transaction()?;
-    for hash in hashes {
-        trace!("Removing {hash}");
-        transaction.execute(
-            &format!("DELETE FROM {} WHERE hash = (?1)", table_name),
-            params![hash],
-        )?;
+    {
+        let mut remove_hash =
+            transaction.prepare(&format!("DELETE FROM {table_name} WHERE hash = (?1)"))?;
+        let mut remove_provenance = transaction.prepare(&format!(
+            "DELETE FROM {table_name}_provenance WHERE hash = (?1)"
+        ))?;
+        for hash in hashes {
+            trace!("Removing {hash}");
+            let hash = hash_to_blob(hash)?;
+            remove_hash.execute(params![hash])?;
+            remove_provenance.execute(params![hash])?;
+        }
     }
     transaction.commit()?;
     Ok(())
 }
 
-/// gets a range of hashes from database
+/// gets a range of hashes from database, leaving out those on the allowlist. the allowlist needs to be attached
 pub fn get_hashes(
     connection: &rusqlite::Connection,
     table_name: String,
@@ -82,16 +296,110 @@ pub fn get_hashes(
     top_index: usize,
 ) -> Result<Vec<String>, rusqlite::Error> {
     let mut sql = connection.prepare(&format!(
-        "SELECT hash FROM {} WHERE id >= ?1 AND id < ?2",
+        "SELECT hash FROM {} WHERE id >= ?1 AND id < ?2 AND {NOT_ALLOWED}",
         table_name
     ))?;
     let hashes: Result<Vec<String>, rusqlite::Error> = sql
-        .query_map(params![bottom_index, top_index], |row| row.get(0))?
+        .query_map(params![bottom_index, top_index], |row| {
+            Ok(blob_to_hash(&row.get::<_, Vec<u8>>(0)?))
+        })?
         .collect();
     let out = hashes.unwrap_or_default();
     Ok(out)
 }
 
+/// gets a range of hashes together with their signature name from database. like get_hashes, it leaves out those on the attached allowlist
+pub fn get_hashes_with_names(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    bottom_index: usize,
+    top_index: usize,
+) -> Result<Vec<(String, Option<String>)>, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!(
+        "SELECT hash, name FROM {table_name} WHERE id >= ?1 AND id < ?2 AND {NOT_ALLOWED}"
+    ))?;
+    let hashes = sql
+        .query_map(params![bottom_index, top_index], |row| {
+            Ok((blob_to_hash(&row.get::<_, Vec<u8>>(0)?), row.get(1)?))
+        })?
+        .collect();
+    hashes
+}
+
+/// gets up to limit hashes of hash_type with an id above after_id together with their id and signature name, ordered by id.
+/// like get_hashes, it leaves out those on the attached allowlist
+pub fn get_hashes_of_type(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    hash_type: &str,
+    after_id: i64,
+    limit: usize,
+) -> Result<Vec<(i64, String, Option<String>)>, rusqlite::Error> {
+    // the hash type is given by the number of bytes of a hash
+    let Some((_, length)) = HASH_TYPES.iter().find(|(name, _)| *name == hash_type) else {
+        return Ok(Vec::new());
+    };
+    let mut sql = connection.prepare(&format!(
+        "SELECT id, hash, name FROM {table_name} WHERE length(hash) = ?1 AND id > ?2 AND {NOT_ALLOWED} ORDER BY id LIMIT ?3"
+    ))?;
+    let hashes = sql
+        .query_map(params![length / 2, after_id, limit], |row| {
+            Ok((
+                row.get(0)?,
+                blob_to_hash(&row.get::<_, Vec<u8>>(1)?),
+                row.get(2)?,
+            ))
+        })?
+        .collect();
+    hashes
+}
+
+/// gets the count of hashes of each hash type in the table, None being those of unknown type
+pub fn get_hash_type_counts(
+    connection: &rusqlite::Connection,
+    table_name: String,
+) -> Result<Vec<(Option<String>, u64)>, rusqlite::Error> {
+    let detected = HASH_TYPES
+        .iter()
+        .map(|(hash_type, length)| format!("WHEN {} THEN '{hash_type}'", length / 2))
+        .collect::<Vec<String>>()
+        .join(" ");
+    let mut sql = connection.prepare(&format!(
+        "SELECT CASE length(hash) {detected} END AS hash_type, COUNT(*) FROM {table_name} GROUP BY hash_type ORDER BY hash_type"
+    ))?;
+    let counts = sql
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect();
+    counts
+}
+
+/// gets up to limit signatures with an id above after_id together with their id, ordered by id. like get_hashes, it leaves out
+/// those on the attached allowlist
+pub fn get_signatures(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    after_id: i64,
+    limit: usize,
+) -> Result<Vec<(i64, Signature)>, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!(
+        "SELECT id, hash, file_size, name, confidence FROM {table_name} WHERE id > ?1 AND {NOT_ALLOWED} ORDER BY id LIMIT ?2"
+    ))?;
+    let signatures = sql
+        .query_map(params![after_id, limit], |row| {
+            Ok((
+                row.get(0)?,
+                Signature {
+                    hash: blob_to_hash(&row.get::<_, Vec<u8>>(1)?),
+                    file_size: row.get(2)?,
+                    name: row.get(3)?,
+                    confidence: row.get(4)?,
+                },
+            ))
+        })?
+        .collect();
+    signatures
+}
+
 /// gets the count of current hashes in database
 pub fn get_hash_count(
     connection: &rusqlite::Connection,
@@ -101,10 +409,178 @@ pub fn get_hash_count(
     sql.query_row([], |row| row.get(0))
 }
 
+/// gets the highest id in the table, 0 if it is empty
+pub fn get_max_id(
+    connection: &rusqlite::Connection,
+    table_name: String,
+) -> Result<i64, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!("SELECT COALESCE(MAX(id), 0) FROM {table_name}"))?;
+    sql.query_row([], |row| row.get(0))
+}
+
+/// opens the allowlist database, which is kept apart from the hashes so it survives cleaning and rebuilding them
+pub fn create_allowlist(
+    allowlist_database: String,
+) -> Result<rusqlite::Connection, rusqlite::Error> {
+    let connection = rusqlite::Connection::open(allowlist_database)?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS allowlist (hash TEXT PRIMARY KEY) WITHOUT ROWID",
+        [],
+    )?;
+    Ok(connection)
+}
+
+/// attaches the allowlist database to connection as allowlist, so hashes on it can be left out
+pub fn attach_allowlist(
+    connection: &rusqlite::Connection,
+    allowlist_database: String,
+) -> Result<(), rusqlite::Error> {
+    // without an allowlist an empty one is attached, so the queries stay the same
+    let allowlist_database = match Path::new(&allowlist_database).exists() {
+        true => allowlist_database,
+        false => ":memory:".to_owned(),
+    };
+    connection.execute(
+        "ATTACH DATABASE ?1 AS allowlist",
+        params![allowlist_database],
+    )?;
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS allowlist.allowlist (hash TEXT PRIMARY KEY) WITHOUT ROWID",
+        [],
+    )?;
+    Ok(())
+}
+
+/// inserts a vector of known-good hashes into the allowlist and returns how many were new
+pub fn insert_allowed_hashes(
+    connection: &mut rusqlite::Connection,
+    hashes: &[String],
+) -> Result<usize, rusqlite::Error> {
+    // we use transactions to speed up large inserts
+    let transaction = connection.transaction()?;
+    let mut inserted = 0;
+    {
+        let mut sql = transaction.prepare("INSERT OR IGNORE INTO allowlist (hash) VALUES (?1)")?;
+        for hash in hashes {
+            trace!("Allowing {hash}");
+            inserted += sql.execute(params![hash.to_lowercase()])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(inserted)
+}
+
+/// copies the md5, sha1 and sha256 hashes of an NSRL RDS SQLite database into the allowlist and returns how many were new
+pub fn import_nsrl(
+    connection: &rusqlite::Connection,
+    nsrl_database: String,
+) -> Result<usize, rusqlite::Error> {
+    connection.execute("ATTACH DATABASE ?1 AS nsrl", params![nsrl_database])?;
+    // RDSv3 offers the distinct hashes as view, other conversions only the FILE table
+    let tables = {
+        let mut sql = connection.prepare("SELECT name FROM nsrl.sqlite_master")?;
+        let tables = sql
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        tables
+    };
+    let source = ["DISTINCT_HASH", "FILE"]
+        .iter()
+        .find_map(|name| tables.iter().find(|table| table.eq_ignore_ascii_case(name)))
+        .ok_or_else(|| {
+            rusqlite::Error::InvalidParameterName(
+                "No DISTINCT_HASH or FILE table in NSRL database".to_owned(),
+            )
+        })?;
+    let columns = {
+        let mut sql = connection.prepare(&format!("PRAGMA nsrl.table_info({source})"))?;
+        let columns = sql
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        columns
+    };
+
+    let mut inserted = 0;
+    for hash_type in ["md5", "sha1", "sha256"] {
+        let Some(column) = columns
+            .iter()
+            .find(|column| column.eq_ignore_ascii_case(hash_type))
+        else {
+            continue;
+        };
+        info!("Copying {hash_type} hashes from NSRL {source}...");
+        inserted += connection.execute(
+            &format!("INSERT OR IGNORE INTO allowlist (hash) SELECT lower({column}) FROM nsrl.{source} WHERE {column} IS NOT NULL AND {column} != ''"),
+            [],
+        )?;
+    }
+    connection.execute("DETACH DATABASE nsrl", [])?;
+    Ok(inserted)
+}
+
+/// checks if hash is on the attached allowlist
+pub fn is_allowed(connection: &rusqlite::Connection, hash: &str) -> Result<bool, rusqlite::Error> {
+    let mut sql = connection
+        .prepare("SELECT EXISTS (SELECT 1 FROM allowlist.allowlist WHERE hash = lower(?1))")?;
+    sql.query_row(params![hash], |row| row.get(0))
+}
+
+/// gets the count of hashes in the table which are on the attached allowlist
+pub fn get_allowed_count(
+    connection: &rusqlite::Connection,
+    table_name: String,
+) -> Result<u64, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!(
+        "SELECT COUNT(*) FROM {table_name} WHERE NOT ({NOT_ALLOWED})"
+    ))?;
+    sql.query_row([], |row| row.get(0))
+}
+
 pub fn cleanup_table(
     connection: &mut rusqlite::Connection,
     table_name: String,
 ) -> Result<(), rusqlite::Error> {
     let _ = connection.execute(&format!("DROP TABLE {}", table_name), [])?;
+    let _ = connection.execute(&format!("DROP TABLE IF EXISTS {table_name}_provenance"), [])?;
+    let _ = connection.execute(&format!("DROP TABLE IF EXISTS {table_name}_sources"), [])?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MD5: &str = "44d88612fea8a8f36de82e1278abb02f";
+    static SHA256: &str = "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f";
+
+    #[test]
+    fn remove_hashes_removes_their_provenance() {
+        let mut connection = create_pool(":memory:".to_owned(), "hashes".to_owned()).unwrap();
+        let hashes = vec![MD5.to_owned(), SHA256.to_owned()];
+        insert_hashes(&mut connection, "hashes".to_owned(), &hashes).unwrap();
+        let source = Source {
+            provider: "manual".to_owned(),
+            file: "eicar.txt".to_owned(),
+            url: None,
+        };
+        insert_provenance(
+            &mut connection,
+            "hashes".to_owned(),
+            &source,
+            &[MD5, SHA256],
+        )
+        .unwrap();
+
+        remove_hashes(&mut connection, "hashes".to_owned(), &vec![MD5.to_owned()]).unwrap();
+        assert_eq!(get_hash_count(&connection, "hashes".to_owned()).unwrap(), 1);
+        assert!(get_provenance(&connection, "hashes".to_owned(), MD5)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            get_provenance(&connection, "hashes".to_owned(), SHA256)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -1,41 +1,71 @@
 use std::{
     fs::{self, DirEntry, File},
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, BufWriter, Write},
     path::Path,
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use log::{debug, error, info, warn};
 
-use crate::organizer::database::{
-    create_pool, get_hash_count, get_hashes, insert_hashes, remove_hashes,
+use crate::{
+    downloader::manifest::{Manifest, MANIFEST_FILE},
+    organizer::{
+        clamav::{is_clamav_file, read_signatures},
+        compression::read_members,
+        database::{
+            attach_allowlist, create_pool, get_allowed_count, get_hash_count, get_hash_type_counts,
+            get_hashes, get_hashes_of_type, get_hashes_with_names, get_max_id, get_signatures,
+            hash_type, insert_hashes, insert_provenance, insert_signatures, remove_hashes,
+            Signature, Source, HASH_TYPES,
+        },
+        normalize::{clean_line, normalize_hash, IngestOptions, Rejects},
+    },
 };
 
-/// inserts the content of provided file into database
-pub fn insert_file(file_path: String, database: String, table_name: String) -> std::io::Result<()> {
+/// inserts the content of provided file into database. compressed files are decompressed while reading,
+/// ClamAV hash databases and containers are inserted together with file size and signature name. lines holding no valid hash
+/// are reported as rejects
+pub fn insert_file(
+    file_path: String,
+    database: String,
+    table_name: String,
+    options: &IngestOptions,
+) -> std::io::Result<()> {
     let start_time = std::time::Instant::now();
 
     let mut database = create_pool(database, table_name.clone())
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    let source = Source {
+        provider: "manual".to_owned(),
+        file: file_path.clone(),
+        url: None,
+    };
 
-    let file = File::open(&file_path)?;
-    let reader = BufReader::new(file);
     // reads line by line from file
-    let mut lines = Vec::new();
-
-    reader.lines().flatten().for_each(|line| {
-        (!line.starts_with('#')).then(|| lines.push(line));
-    });
+    let mut batch = Batch::default();
+    batch.read_file(Path::new(&file_path), options.hash_type.as_deref())?;
+    batch
+        .rejects
+        .report(Path::new(&options.rejects_file), options.strict)?;
 
     info!(
         "Inserting file {} containing {} hashes into database...",
         file_path,
-        lines.len()
+        batch.lines.len() + batch.signatures.len()
     );
 
     // insert into database
-    insert_hashes(&mut database, table_name.clone(), &lines)
+    insert_hashes(&mut database, table_name.clone(), &batch.lines)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    insert_signatures(&mut database, table_name.clone(), &batch.signatures)
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    insert_provenance(
+        &mut database,
+        table_name.clone(),
+        &source,
+        &hashes_of(&batch.lines, &batch.signatures),
+    )
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
 
     info!(
         "Inserted file in {}s",
@@ -46,20 +76,45 @@ pub fn insert_file(file_path: String, database: String, table_name: String) -> s
     Ok(())
 }
 
-/// inserts the content of files in provided folder into database
+/// inserts the content of files in provided folder into database. compressed files are decompressed while reading,
+/// ClamAV hash databases and containers are inserted together with file size and signature name. like with insert_file,
+/// lines holding no valid hash are reported as rejects. in strict mode nothing is inserted if any file holds such a line
 pub fn insert_files(
     tmp_dir: String,
     max_file_combines: usize,
     database: String,
     table_name: String,
+    options: &IngestOptions,
 ) -> std::io::Result<()> {
     let start_time = std::time::Instant::now();
 
-    // get all files from a folder
+    // get all files from a folder, skipping subfolders like the raw provider downloads, hidden files like the fetch state and the fetch manifest
     let entries: Vec<DirEntry> = fs::read_dir(Path::new(&tmp_dir))?
         .filter_map(Result::ok)
+        .filter(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            entry.path().is_file() && !file_name.starts_with('.') && file_name != MANIFEST_FILE
+        })
         .collect();
     let output_dir = Path::new(&tmp_dir);
+    // the manifest of the last fetch tells which provider and url each file came from
+    let manifest = Manifest::load(output_dir).unwrap_or_default();
+
+    // the files are inserted in chunks, so strict mode checks all of them first to not leave the insert half done
+    if options.strict {
+        let mut rejects = Rejects::default();
+        for entry in &entries {
+            let mut batch = Batch::default();
+            // files which cannot be read are skipped when inserting as well
+            if batch
+                .read_file(&entry.path(), options.hash_type.as_deref())
+                .is_ok()
+            {
+                rejects.append(&mut batch.rejects);
+            }
+        }
+        rejects.report(Path::new(&options.rejects_file), true)?;
+    }
 
     let mut database = create_pool(database, table_name.clone())
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
@@ -71,7 +126,9 @@ pub fn insert_files(
         let end = std::cmp::min((chunk_id + 1) * max_file_combines, entries.len());
 
         // read all files line by line into buffer
-        let mut lines: Vec<String> = Vec::new();
+        let mut batch = Batch::default();
+        // source of each file together with the ranges of lines and signatures read from it
+        let mut sources = Vec::new();
         for file_id in start..end {
             let reader_path = output_dir.join(
                 entries
@@ -82,35 +139,49 @@ pub fn insert_files(
                     .file_name(),
             );
             debug!("Adding {} to batch", reader_path.display());
-            let file = match File::open(&reader_path) {
-                Ok(file) => file,
-                Err(err) => {
-                    error!(
-                        "Could not open file {} for reading: {err}",
-                        reader_path.display()
-                    );
-                    continue;
-                }
-            };
-            let reader = BufReader::new(file);
-            reader.lines().flatten().for_each(|line| {
-                (!line.starts_with('#')).then(|| lines.push(line));
-            });
+            let (lines_start, signatures_start) = (batch.lines.len(), batch.signatures.len());
+            if let Err(err) = batch.read_file(&reader_path, options.hash_type.as_deref()) {
+                error!("Could not read file {}: {err}", reader_path.display());
+            }
+            sources.push((
+                fetched_source(&manifest, &reader_path),
+                lines_start..batch.lines.len(),
+                signatures_start..batch.signatures.len(),
+            ));
         }
+        batch
+            .rejects
+            .report(Path::new(&options.rejects_file), options.strict)?;
 
         info!(
             "Inserting chunk {}/{} containing {} hashes into database...",
             chunk_id,
             (entries.len() / max_file_combines),
-            lines.len()
+            batch.lines.len() + batch.signatures.len()
         );
         // insert into databse
-        match insert_hashes(&mut database, table_name.clone(), &lines) {
+        match insert_hashes(&mut database, table_name.clone(), &batch.lines) {
             Ok(_) => {}
             Err(err) => {
                 warn!("Error inserting: {err}");
             }
         }
+        if let Err(err) = insert_signatures(&mut database, table_name.clone(), &batch.signatures) {
+            warn!("Error inserting signatures: {err}");
+        }
+        for (source, lines_range, signatures_range) in sources {
+            if let Err(err) = insert_provenance(
+                &mut database,
+                table_name.clone(),
+                &source,
+                &hashes_of(
+                    &batch.lines[lines_range],
+                    &batch.signatures[signatures_range],
+                ),
+            ) {
+                warn!("Error recording provenance of {}: {err}", source.file);
+            }
+        }
     }
     info!(
         "Building database took {}s",
@@ -121,23 +192,139 @@ pub fn insert_files(
     Ok(())
 }
 
-/// patches the database with the supplied file
-pub fn patch(database: String, table_name: String, file_name: String) -> std::io::Result<()> {
+/// hashes and signatures read from input files, along with the lines holding no valid hash
+#[derive(Default)]
+struct Batch {
+    lines: Vec<String>,
+    signatures: Vec<Signature>,
+    rejects: Rejects,
+}
+
+impl Batch {
+    /// reads a file, which may be compressed or a ClamAV database, into the batch. hashes not fitting hash_type are rejected if it is given
+    fn read_file(&mut self, path: &Path, hash_type: Option<&str>) -> std::io::Result<()> {
+        if is_clamav_file(path) {
+            let mut signatures = read_signatures(path, hash_type, &mut self.rejects)?;
+            self.signatures.append(&mut signatures);
+            return Ok(());
+        }
+        let file = path.display().to_string();
+        read_members(path, |reader| {
+            // lines are split by hand, so lines which are no valid UTF-8 get rejected instead of ending the file
+            for (index, line) in reader.split(b'\n').enumerate() {
+                match String::from_utf8(line?) {
+                    Ok(line) => self.collect_line(&file, index + 1, &line, hash_type),
+                    Err(err) => self.rejects.push(
+                        &file,
+                        index + 1,
+                        "not valid UTF-8".to_owned(),
+                        &String::from_utf8_lossy(err.as_bytes()),
+                    ),
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// sorts a line of an input file into plain hashes and signatures staged by providers as hash, name and confidence separated by tabs.
+    /// blank lines and comments are skipped, lines holding no valid hash rejected
+    fn collect_line(&mut self, file: &str, number: usize, line: &str, hash_type: Option<&str>) {
+        let line = clean_line(line);
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        let mut fields = line.split('\t');
+        let hash = match normalize_hash(fields.next().unwrap_or_default().trim(), hash_type) {
+            Ok(hash) => hash,
+            Err(reason) => return self.rejects.push(file, number, reason, line),
+        };
+        if !line.contains('\t') {
+            self.lines.push(hash);
+            return;
+        }
+        self.signatures.push(Signature {
+            hash,
+            file_size: None,
+            name: fields
+                .next()
+                .filter(|name| !name.is_empty())
+                .map(str::to_owned),
+            confidence: fields
+                .next()
+                .and_then(|confidence| confidence.parse::<u8>().ok()),
+        });
+    }
+}
+
+/// returns the hashes of plain lines and signatures, for recording where they came from
+fn hashes_of<'a>(lines: &'a [String], signatures: &'a [Signature]) -> Vec<&'a str> {
+    lines
+        .iter()
+        .map(String::as_str)
+        .chain(signatures.iter().map(|signature| signature.hash.as_str()))
+        .collect()
+}
+
+/// looks up the provider and url of a file in the tmp workfolder in the manifest of the last fetch. files staged by a provider
+/// share the part of their name before the first dot with the fetched file, e.g. mb_full.md5 with mb_full.zip
+fn fetched_source(manifest: &Manifest, path: &Path) -> Source {
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = |file_name: &str| file_name.split('.').next().unwrap_or_default().to_owned();
+    let entry = manifest
+        .files
+        .iter()
+        .find(|entry| entry.remote_file.file_name == file_name)
+        .or_else(|| {
+            manifest
+                .files
+                .iter()
+                .find(|entry| stem(&entry.remote_file.file_name) == stem(&file_name))
+        });
+    Source {
+        provider: entry
+            .map(|entry| entry.provider.clone())
+            .unwrap_or("manual".to_owned()),
+        file: file_name,
+        url: entry.map(|entry| entry.remote_file.url.clone()),
+    }
+}
+
+/// patches the database with the supplied file. hashes are checked like inserted ones, invalid ones reported as rejects
+pub fn patch(
+    database: String,
+    table_name: String,
+    file_name: String,
+    options: &IngestOptions,
+) -> std::io::Result<()> {
     let start_time = std::time::Instant::now();
-    let file = File::open(file_name)?;
+    let file = File::open(&file_name)?;
     let bufreader = BufReader::new(file);
 
     let mut add = Vec::new();
     let mut remove = Vec::new();
+    let mut rejects = Rejects::default();
 
     // we read over each line
-    for line in bufreader.lines().flatten() {
-        match line {
-            _ if line.starts_with('+') => add.push(line.replacen('+', "", 1).trim().to_owned()),
-            _ if line.starts_with('-') => remove.push(line.replacen('-', "", 1).trim().to_owned()),
-            _ => warn!("Ignoring line {line}"),
+    for (index, line) in bufreader.lines().enumerate() {
+        let line = line?;
+        let line = clean_line(&line);
+        let (list, hash) = match line {
+            _ if line.starts_with('+') => (&mut add, &line[1..]),
+            _ if line.starts_with('-') => (&mut remove, &line[1..]),
+            _ => {
+                warn!("Ignoring line {line}");
+                continue;
+            }
+        };
+        match normalize_hash(hash.trim(), options.hash_type.as_deref()) {
+            Ok(hash) => list.push(hash),
+            Err(reason) => rejects.push(&file_name, index + 1, reason, line),
         }
     }
+    rejects.report(Path::new(&options.rejects_file), options.strict)?;
 
     // insert into database
     let mut database = create_pool(database, table_name.clone())
@@ -146,6 +333,17 @@ pub fn patch(database: String, table_name: String, file_name: String) -> std::io
     info!("Adding {} hashes from patch...", add.len());
     insert_hashes(&mut database, table_name.clone(), &add)
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    insert_provenance(
+        &mut database,
+        table_name.clone(),
+        &Source {
+            provider: "patch".to_owned(),
+            file: file_name,
+            url: None,
+        },
+        &hashes_of(&add, &[]),
+    )
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
 
     info!("Removing {} hashes from patch...", remove.len());
     remove_hashes(&mut database, table_name, &remove)
@@ -160,16 +358,20 @@ pub fn patch(database: String, table_name: String, file_name: String) -> std::io
     Ok(())
 }
 
-/// writes the database hashes to output files
+/// writes the database hashes to output files, leaving out those on the allowlist. with_names follows each hash with its signature
+/// name, if known, separated by a tab. split_hash_types writes each hash type into a subfolder named after it, e.g. md5/00000
 pub fn write_files(
     output_dir_string: String,
     file_size: usize,
     database: String,
     table_name: String,
+    allowlist_database: String,
+    with_names: bool,
+    split_hash_types: bool,
 ) -> std::io::Result<()> {
     let start_time = std::time::Instant::now();
     let output_dir = Path::new(&output_dir_string);
-    // removes director if exist
+    // removes the directory if it exists
     if output_dir.exists() {
         fs::remove_dir_all(output_dir)?;
     }
@@ -178,28 +380,83 @@ pub fn write_files(
     // setup connection
     let connection = create_pool(database, table_name.clone())
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    attach_allowlist(&connection, allowlist_database)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
 
     let count = get_hash_count(&connection, table_name.clone())
         .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
-    info!("Exporting {count} hashes...");
+    let allowed = get_allowed_count(&connection, table_name.clone())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    info!(
+        "Exporting {} hashes, suppressing {allowed} on the allowlist...",
+        count - allowed
+    );
+
+    match split_hash_types {
+        true => write_hash_types(&connection, table_name, output_dir, file_size, with_names)?,
+        false => write_frames(
+            &connection,
+            table_name,
+            &output_dir_string,
+            file_size,
+            with_names,
+        )?,
+    }
+    info!(
+        "Writing output files took {}s",
+        std::time::Instant::now()
+            .duration_since(start_time)
+            .as_secs()
+    );
+    Ok(())
+}
+
+/// writes the hashes into output files, one for each frame of file_size ids
+fn write_frames(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    output_dir_string: &str,
+    file_size: usize,
+    with_names: bool,
+) -> std::io::Result<()> {
+    let max_id = get_max_id(connection, table_name.clone())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
 
+    // empty frames would never reach max_id
+    let file_size = file_size.max(1);
     let mut current_frame = 0;
     let mut current_file = 0;
     loop {
         // create frames
         let bottom = current_frame * file_size;
         let top = bottom + file_size;
+        // if all frames have been read, we are done
+        if bottom as i64 > max_id {
+            break;
+        }
         // fetch hashes for current frame from
-        let hashes = get_hashes(&connection, table_name.clone(), bottom, top).map_err(|err| {
+        let hashes = match with_names {
+            true => {
+                get_hashes_with_names(connection, table_name.clone(), bottom, top).map(|hashes| {
+                    hashes
+                        .into_iter()
+                        .map(|(hash, name)| export_line(hash, name))
+                        .collect()
+                })
+            }
+            false => get_hashes(connection, table_name.clone(), bottom, top),
+        }
+        .map_err(|err| {
             std::io::Error::new(
                 std::io::ErrorKind::Other,
                 format!("Failed to fetch hashes from database: {err}"),
             )
         })?;
 
-        // if no more hashes have been found, we are done
+        // frames are empty if all their hashes are on the allowlist
         if hashes.is_empty() {
-            break;
+            current_frame += 1;
+            continue;
         }
         // determining output filename
         let mut file = File::create(Path::new(&format!(
@@ -213,8 +470,158 @@ pub fn write_files(
         current_file += 1;
         current_frame += 1;
     }
+    Ok(())
+}
+
+/// writes the hashes of each hash type into output files of file_size lines in a subfolder named after the type.
+/// hashes of unknown type are left out
+fn write_hash_types(
+    connection: &rusqlite::Connection,
+    table_name: String,
+    output_dir: &Path,
+    file_size: usize,
+    with_names: bool,
+) -> std::io::Result<()> {
+    for (hash_type, _) in HASH_TYPES {
+        let type_dir = output_dir.join(hash_type);
+        fs::create_dir_all(&type_dir)?;
+
+        let mut last_id = 0;
+        let mut current_file = 0;
+        loop {
+            // fetch the hashes for the current file, continuing after the last one written
+            let hashes = get_hashes_of_type(
+                connection,
+                table_name.clone(),
+                hash_type,
+                last_id,
+                file_size.max(1),
+            )
+            .map_err(|err| {
+                std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    format!("Failed to fetch hashes from database: {err}"),
+                )
+            })?;
+
+            // if no more hashes have been found, we are done
+            let Some((id, _, _)) = hashes.last() else {
+                break;
+            };
+            last_id = *id;
+
+            let file_path = type_dir.join(format!("{:0>5}", current_file));
+            info!("Writing to {}", file_path.display());
+            let mut file = BufWriter::new(File::create(&file_path)?);
+            for (_, hash, name) in hashes {
+                match with_names {
+                    true => writeln!(file, "{}", export_line(hash, name))?,
+                    false => writeln!(file, "{hash}")?,
+                }
+            }
+            file.flush()?;
+            current_file += 1;
+        }
+    }
+
+    let unknown = get_hash_type_counts(connection, table_name)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?
+        .into_iter()
+        .filter(|(hash_type, _)| hash_type.is_none())
+        .map(|(_, count)| count)
+        .sum::<u64>();
+    (unknown > 0).then(|| warn!("Skipped {unknown} hashes which are no md5, sha1 or sha256"));
+    Ok(())
+}
+
+/// formats a hash for output files written with names, followed by its signature name if known
+fn export_line(hash: String, name: Option<String>) -> String {
+    match name {
+        // the name has to stay on the line of its hash
+        Some(name) => format!("{hash}\t{}", name.replace(['\t', '\r', '\n'], " ")),
+        None => hash,
+    }
+}
+
+/// writes the database hashes as YARA rules using the hash module to output files, rules_per_file rules each.
+/// like write_files, it leaves out hashes on the allowlist
+pub fn write_yara(
+    output_dir_string: String,
+    rules_per_file: usize,
+    database: String,
+    table_name: String,
+    allowlist_database: String,
+) -> std::io::Result<()> {
+    let start_time = std::time::Instant::now();
+    let output_dir = Path::new(&output_dir_string);
+    // removes the directory if it exists
+    if output_dir.exists() {
+        fs::remove_dir_all(output_dir)?;
+    }
+    fs::create_dir_all(output_dir)?;
+
+    // setup connection
+    let connection = create_pool(database, table_name.clone())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+
+    attach_allowlist(&connection, allowlist_database)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+
+    let count = get_hash_count(&connection, table_name.clone())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
+    let allowed = get_allowed_count(&connection, table_name.clone())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err.to_string()))?;
     info!(
-        "Writing output files took {}s",
+        "Exporting {} hashes as YARA rules, suppressing {allowed} on the allowlist...",
+        count - allowed
+    );
+
+    let mut last_id = 0;
+    let mut current_file = 0;
+    let mut skipped = 0;
+    loop {
+        // fetch the signatures for the current file, continuing after the last one written
+        let signatures = get_signatures(
+            &connection,
+            table_name.clone(),
+            last_id,
+            rules_per_file.max(1),
+        )
+        .map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Failed to fetch hashes from database: {err}"),
+            )
+        })?;
+
+        // if no more hashes have been found, we are done
+        let Some((id, _)) = signatures.last() else {
+            break;
+        };
+        last_id = *id;
+
+        let file_path = output_dir.join(format!("{:0>5}.yar", current_file));
+        info!("Writing to {}", file_path.display());
+        let mut file = BufWriter::new(File::create(&file_path)?);
+        writeln!(file, "import \"hash\"")?;
+        for (id, signature) in &signatures {
+            match yara_rule(*id, signature) {
+                Some(rule) => write!(file, "\n{rule}")?,
+                None => {
+                    debug!(
+                        "Skipping {} as it is no md5, sha1 or sha256",
+                        signature.hash
+                    );
+                    skipped += 1;
+                }
+            }
+        }
+        file.flush()?;
+        current_file += 1;
+    }
+    (skipped > 0).then(|| warn!("Skipped {skipped} hashes which are no md5, sha1 or sha256"));
+    info!(
+        "Writing YARA rules took {}s",
         std::time::Instant::now()
             .duration_since(start_time)
             .as_secs()
@@ -222,6 +629,70 @@ pub fn write_files(
     Ok(())
 }
 
+/// formats a signature as YARA rule matching files with its hash. None if the hash is no md5, sha1 or sha256
+fn yara_rule(id: i64, signature: &Signature) -> Option<String> {
+    let hash = signature.hash.to_lowercase();
+    // the hash module names its functions like the hash types
+    let function = hash_type(&hash)?;
+    // rule names have to be unique, so the id is appended
+    let rule_name = match &signature.name {
+        Some(name) => format!("{}_{id}", yara_identifier(name)),
+        None => format!("{function}_{id}"),
+    };
+
+    let mut meta = Vec::new();
+    if let Some(name) = &signature.name {
+        meta.push(format!("name = \"{}\"", yara_string(name)));
+    }
+    meta.push(format!("hash = \"{hash}\""));
+    if let Some(confidence) = signature.confidence {
+        meta.push(format!("confidence = {confidence}"));
+    }
+    let mut condition = format!("hash.{function}(0, filesize) == \"{hash}\"");
+    if let Some(file_size) = signature.file_size {
+        meta.push(format!("file_size = {file_size}"));
+        // checking the size first spares hashing files which cannot match
+        condition = format!("filesize == {file_size} and {condition}");
+    }
+
+    Some(format!(
+        "rule {rule_name}\n{{\n    meta:\n{}    condition:\n        {condition}\n}}\n",
+        meta.iter()
+            .map(|line| format!("        {line}\n"))
+            .collect::<String>()
+    ))
+}
+
+/// turns name into a valid rule identifier by replacing everything but letters, digits and underscores
+fn yara_identifier(name: &str) -> String {
+    let identifier = name
+        .chars()
+        // identifiers are limited to 128 characters, leaving room for the id
+        .take(100)
+        .map(|char| match char {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => char,
+            _ => '_',
+        })
+        .collect::<String>();
+    // identifiers cannot start with a digit
+    match identifier.starts_with(|char: char| char.is_ascii_digit()) {
+        true => format!("_{identifier}"),
+        false => identifier,
+    }
+}
+
+/// escapes text for use inside a YARA string
+fn yara_string(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'"' => "\\\"".to_owned(),
+            b'\\' => "\\\\".to_owned(),
+            b' '..=b'~' => (byte as char).to_string(),
+            _ => format!("\\x{byte:02x}"),
+        })
+        .collect()
+}
+
 /// writes a timestamp file to the output repository
 pub fn set_timestamp(output_dir: String) -> std::io::Result<()> {
     let current_timestamp = SystemTime::now()
@@ -236,3 +707,117 @@ pub fn set_timestamp(output_dir: String) -> std::io::Result<()> {
     let mut file = File::create(timestamp)?;
     file.write_all(format!("{current_timestamp}").as_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organizer::database::get_hash_count;
+
+    #[test]
+    fn strict_insert_files_inserts_nothing_if_any_file_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let tmp_dir = dir.path().join("tmp");
+        fs::create_dir(&tmp_dir).unwrap();
+        fs::write(
+            tmp_dir.join("a_good.md5"),
+            "44d88612fea8a8f36de82e1278abb02f\n",
+        )
+        .unwrap();
+        fs::write(tmp_dir.join("b_bad.md5"), "<html>\n").unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let options = IngestOptions {
+            hash_type: None,
+            rejects_file: path("rejects.txt"),
+            strict: true,
+        };
+
+        // one file per chunk, so the good file would be inserted before the bad one is read
+        let err = insert_files(
+            tmp_dir.to_string_lossy().into_owned(),
+            1,
+            path("hashes_db"),
+            "hashes".to_owned(),
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            fs::read_to_string(path("rejects.txt"))
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+        let connection = create_pool(path("hashes_db"), "hashes".to_owned()).unwrap();
+        assert_eq!(get_hash_count(&connection, "hashes".to_owned()).unwrap(), 0);
+    }
+
+    #[test]
+    fn yara_rule_checks_file_size_before_hashing() {
+        let signature = Signature {
+            hash: "44D88612FEA8A8F36DE82E1278ABB02F".to_owned(),
+            file_size: Some(68),
+            name: Some("Win.Test.EICAR_HDB-1".to_owned()),
+            confidence: Some(90),
+        };
+        assert_eq!(
+            yara_rule(7, &signature).unwrap(),
+            r#"rule Win_Test_EICAR_HDB_1_7
+{
+    meta:
+        name = "Win.Test.EICAR_HDB-1"
+        hash = "44d88612fea8a8f36de82e1278abb02f"
+        confidence = 90
+        file_size = 68
+    condition:
+        filesize == 68 and hash.md5(0, filesize) == "44d88612fea8a8f36de82e1278abb02f"
+}
+"#
+        );
+    }
+
+    #[test]
+    fn yara_rule_without_name_or_size() {
+        let signature = Signature {
+            hash: "3395856ce81f2b7382dee72602f798b642f14140".to_owned(),
+            file_size: None,
+            name: None,
+            confidence: None,
+        };
+        assert_eq!(
+            yara_rule(3, &signature).unwrap(),
+            r#"rule sha1_3
+{
+    meta:
+        hash = "3395856ce81f2b7382dee72602f798b642f14140"
+    condition:
+        hash.sha1(0, filesize) == "3395856ce81f2b7382dee72602f798b642f14140"
+}
+"#
+        );
+
+        // not a hash the hash module knows
+        let signature = Signature {
+            hash: "abc".to_owned(),
+            ..signature
+        };
+        assert_eq!(yara_rule(3, &signature), None);
+    }
+
+    #[test]
+    fn yara_identifier_replaces_invalid_characters() {
+        assert_eq!(yara_identifier("Win.Trojan.Agent-1"), "Win_Trojan_Agent_1");
+        assert_eq!(yara_identifier("Mirai botnet (ü)"), "Mirai_botnet____");
+        // identifiers cannot start with a digit
+        assert_eq!(yara_identifier("7ev3n"), "_7ev3n");
+        assert_eq!(yara_identifier(&"a".repeat(200)).len(), 100);
+    }
+
+    #[test]
+    fn yara_string_escapes_quotes_backslashes_and_non_printable_bytes() {
+        assert_eq!(yara_string("plain text"), "plain text");
+        assert_eq!(yara_string(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(yara_string(r"C:\temp"), r"C:\\temp");
+        assert_eq!(yara_string("tab\tü"), r"tab\x09\xc3\xbc");
+    }
+}
@@ -0,0 +1,303 @@
+use log::{debug, info, warn};
+
+/// a change of the schema, applied to a single hash table
+type Migration = fn(&rusqlite::Connection, &str) -> Result<(), rusqlite::Error>;
+
+/// all migrations in the order they have to be applied. the schema version of a database, kept in its user_version, is the
+/// number of migrations applied to it, so new ones have to be appended
+static MIGRATIONS: [(&str, Migration); 1] = [(
+    "Store hashes once as blobs with signature columns",
+    convert_hash_table,
+)];
+
+/// the schema version of databases created by this version
+pub fn latest_schema_version() -> usize {
+    MIGRATIONS.len()
+}
+
+/// gets the schema version of the database
+pub fn get_schema_version(connection: &rusqlite::Connection) -> Result<usize, rusqlite::Error> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// applies the migrations the database lacks to all of its hash tables, each migration in its own transaction
+pub fn migrate(connection: &mut rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    let version = get_schema_version(connection)?;
+    if version > latest_schema_version() {
+        warn!(
+            "Database has schema version {version}, which is newer than {} this version knows",
+            latest_schema_version()
+        );
+        return Ok(());
+    }
+
+    // new databases are created with the latest layout
+    if version < latest_schema_version() && get_hash_tables(connection)?.is_empty() {
+        debug!(
+            "Setting schema version of new database to {}",
+            latest_schema_version()
+        );
+        return connection.execute_batch(&format!(
+            "PRAGMA user_version = {}",
+            latest_schema_version()
+        ));
+    }
+
+    for (index, (description, migration)) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        let tables = get_hash_tables(&transaction)?;
+        info!(
+            "Migrating to schema version {}: {description}...",
+            index + 1
+        );
+        for table_name in &tables {
+            migration(&transaction, table_name)?;
+        }
+        // pragmas take no parameters
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+        transaction.commit()?;
+    }
+    // migrations rewrite whole tables, the space they freed is only given back by vacuuming
+    if version < latest_schema_version() {
+        info!("Compacting database...");
+        connection.execute_batch("VACUUM")?;
+    }
+    Ok(())
+}
+
+/// gets the names of all tables holding hashes, which are those with an id and a hash column
+pub fn get_hash_tables(connection: &rusqlite::Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let mut sql = connection.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )?;
+    let tables = sql
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    let mut hash_tables = Vec::new();
+    for table_name in tables {
+        let columns = get_columns(connection, &table_name)?;
+        if columns.iter().any(|column| column == "id")
+            && columns.iter().any(|column| column == "hash")
+        {
+            hash_tables.push(table_name);
+        }
+    }
+    Ok(hash_tables)
+}
+
+/// gets the column names of a table
+fn get_columns(
+    connection: &rusqlite::Connection,
+    table_name: &str,
+) -> Result<Vec<String>, rusqlite::Error> {
+    let mut sql = connection.prepare(&format!("PRAGMA table_info({table_name})"))?;
+    let columns = sql.query_map([], |row| row.get::<_, String>(1))?.collect();
+    columns
+}
+
+/// adds a column to an existing table unless it already has it
+fn add_column_if_missing(
+    connection: &rusqlite::Connection,
+    table_name: &str,
+    column: &str,
+    column_type: &str,
+) -> Result<(), rusqlite::Error> {
+    if !get_columns(connection, table_name)?
+        .iter()
+        .any(|existing| existing == column)
+    {
+        info!("Adding column {column} to {table_name}...");
+        connection.execute(
+            &format!("ALTER TABLE {table_name} ADD COLUMN {column} {column_type}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// converts a table of the first version, which held hashes as hex text and possibly several times, to the current layout. adds
+/// the columns of signatures and the first and latest insert, which stay unknown for hashes already in the table, removes hashes
+/// which are no valid hex and duplicates, also differing only in case, stores the rest as raw bytes, which halves their size, and
+/// adds the unique index on hash
+fn convert_hash_table(
+    connection: &rusqlite::Connection,
+    table_name: &str,
+) -> Result<(), rusqlite::Error> {
+    add_column_if_missing(connection, table_name, "file_size", "INTEGER")?;
+    add_column_if_missing(connection, table_name, "name", "TEXT")?;
+    add_column_if_missing(connection, table_name, "confidence", "INTEGER")?;
+    add_column_if_missing(connection, table_name, "first_seen", "INTEGER")?;
+    add_column_if_missing(connection, table_name, "last_seen", "INTEGER")?;
+
+    let removed = connection.execute(
+        &format!("DELETE FROM {table_name} WHERE typeof(hash) = 'text' AND (hash = '' OR unhex(hash) IS NULL)"),
+        [],
+    )?;
+    (removed > 0)
+        .then(|| warn!("Removed {removed} hashes from {table_name} which are no valid hex"));
+    info!("Removing duplicates from {table_name}...");
+    let removed = connection.execute(
+        &format!("DELETE FROM {table_name} WHERE rowid NOT IN (SELECT MIN(rowid) FROM {table_name} GROUP BY lower(hash))"),
+        [],
+    )?;
+    info!("Removed {removed} duplicates");
+    info!("Converting hashes of {table_name} to blobs...");
+    connection.execute(
+        &format!("UPDATE {table_name} SET hash = unhex(hash) WHERE typeof(hash) = 'text'"),
+        [],
+    )?;
+    connection.execute(
+        &format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {table_name}_hash_unique ON {table_name} (hash)"
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rusqlite::params;
+
+    use super::*;
+    use crate::organizer::{
+        database::{create_pool, get_hash_type_counts},
+        files::write_files,
+    };
+
+    /// the lines of all files an export wrote into output_dir, in order
+    fn exported(output_dir: &std::path::Path) -> Vec<String> {
+        let mut files = fs::read_dir(output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "timestamp")
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+            .iter()
+            .flat_map(|path| {
+                fs::read_to_string(path)
+                    .unwrap()
+                    .lines()
+                    .map(str::to_owned)
+                    .collect::<Vec<String>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn new_database_gets_latest_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("hashes_db").to_string_lossy().into_owned();
+        let connection = create_pool(database, "hashes".to_owned()).unwrap();
+        assert_eq!(
+            get_schema_version(&connection).unwrap(),
+            latest_schema_version()
+        );
+    }
+
+    #[test]
+    fn migrates_version_0_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let (a, b, c) = (
+            format!("{:0>32x}", 10),
+            format!("{:0>32x}", 11),
+            format!("{:0>64x}", 12),
+        );
+        // duplicates, also differing in case, and lines which are no hash, as older versions inserted them
+        let rows = [
+            a.clone(),
+            b.to_uppercase(),
+            a.clone(),
+            a.to_uppercase(),
+            "nothex!!".to_owned(),
+            String::new(),
+            c.clone(),
+            b.clone(),
+        ];
+        {
+            let connection = rusqlite::Connection::open(path("hashes_db")).unwrap();
+            // the table as created by the first version, which had no unique index
+            connection
+                .execute(
+                    "CREATE TABLE hashes (id INTEGER PRIMARY KEY, hash TEXT NOT NULL)",
+                    [],
+                )
+                .unwrap();
+            for row in &rows {
+                connection
+                    .execute("INSERT INTO hashes (hash) VALUES (?1)", params![row])
+                    .unwrap();
+            }
+            assert_eq!(get_schema_version(&connection).unwrap(), 0);
+        }
+
+        let connection = create_pool(path("hashes_db"), "hashes".to_owned()).unwrap();
+        assert_eq!(
+            get_schema_version(&connection).unwrap(),
+            latest_schema_version()
+        );
+        // the first of each hash is kept, lowercased and stored as bytes
+        let stored = connection
+            .prepare("SELECT id, typeof(hash), lower(hex(hash)) FROM hashes ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i64, String, String)>, rusqlite::Error>>()
+            .unwrap();
+        assert_eq!(
+            stored,
+            [
+                (1, "blob".to_owned(), a.clone()),
+                (2, "blob".to_owned(), b.clone()),
+                (7, "blob".to_owned(), c.clone()),
+            ]
+        );
+        assert_eq!(
+            get_columns(&connection, "hashes").unwrap(),
+            [
+                "id",
+                "hash",
+                "file_size",
+                "name",
+                "confidence",
+                "first_seen",
+                "last_seen"
+            ]
+        );
+        assert_eq!(
+            get_hash_type_counts(&connection, "hashes".to_owned()).unwrap(),
+            [(Some("md5".to_owned()), 2), (Some("sha256".to_owned()), 1)]
+        );
+
+        drop(connection);
+
+        // the export holds every valid hash once, in the order it was first inserted
+        for file_size in [1_000_000, 2] {
+            write_files(
+                path("output"),
+                file_size,
+                path("hashes_db"),
+                "hashes".to_owned(),
+                path("allowlist_db"),
+                false,
+                false,
+            )
+            .unwrap();
+            assert_eq!(
+                exported(&dir.path().join("output")),
+                [a.clone(), b.clone(), c.clone()]
+            );
+        }
+
+        // migrating again changes nothing
+        let connection = create_pool(path("hashes_db"), "hashes".to_owned()).unwrap();
+        let count: u64 = connection
+            .query_row("SELECT COUNT(*) FROM hashes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+}
@@ -1,2 +1,7 @@
+pub mod allowlist;
+pub mod clamav;
+pub mod compression;
 pub mod database;
 pub mod files;
+pub mod migrations;
+pub mod normalize;
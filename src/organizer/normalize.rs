@@ -0,0 +1,130 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use log::warn;
+
+use crate::organizer::database::HASH_TYPES;
+
+/// settings shared by every path hashes are inserted on
+#[derive(Clone, Debug, Default)]
+pub struct IngestOptions {
+    /// hash type all inserted hashes must have; any known one if None
+    pub hash_type: Option<String>,
+    /// file the rejected lines are appended to
+    pub rejects_file: String,
+    /// fails inserting if any line was rejected
+    pub strict: bool,
+}
+
+/// a line of an input file which holds no valid hash
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reject {
+    pub file: String,
+    /// number of the line, starting at 1
+    pub line: usize,
+    pub reason: String,
+    pub content: String,
+}
+
+/// the lines rejected while reading input files
+#[derive(Debug, Default)]
+pub struct Rejects {
+    entries: Vec<Reject>,
+}
+
+impl Rejects {
+    pub fn push(&mut self, file: &str, line: usize, reason: String, content: &str) {
+        self.entries.push(Reject {
+            file: file.to_owned(),
+            line,
+            reason,
+            content: content.to_owned(),
+        });
+    }
+
+    /// moves the entries of other into these rejects
+    pub fn append(&mut self, other: &mut Rejects) {
+        self.entries.append(&mut other.entries);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// appends the rejected lines to the report at path as file:line, reason and content separated by tabs, warns about them
+    /// and fails if strict is set
+    pub fn report(&self, path: &Path, strict: bool) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+        for reject in &self.entries {
+            // the content may be anything, like a line of an html page
+            let content = reject
+                .content
+                .chars()
+                .take(200)
+                .map(|char| match char.is_control() {
+                    true => ' ',
+                    false => char,
+                })
+                .collect::<String>();
+            writeln!(
+                writer,
+                "{}:{}\t{}\t{content}",
+                reject.file, reject.line, reject.reason
+            )?;
+        }
+        writer.flush()?;
+        warn!(
+            "Rejected {} lines which hold no valid hash, see {}",
+            self.len(),
+            path.display()
+        );
+        match strict {
+            true => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Rejected {} lines in strict mode, see {}",
+                    self.len(),
+                    path.display()
+                ),
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+/// strips the byte order mark and surrounding whitespace, including the \r of CRLF files, from a line
+pub fn clean_line(line: &str) -> &str {
+    line.trim_start_matches('\u{feff}').trim()
+}
+
+/// turns the hash of a cleaned line into lowercase hex, checking its length against the given hash type or, if None, against
+/// any known one. returns why it is no valid hash otherwise
+pub fn normalize_hash(hash: &str, hash_type: Option<&str>) -> Result<String, String> {
+    if hash.is_empty() {
+        return Err("empty hash".to_owned());
+    }
+    if !hash.chars().all(|char| char.is_ascii_hexdigit()) {
+        return Err("not hexadecimal".to_owned());
+    }
+    let valid_length = HASH_TYPES
+        .iter()
+        .filter(|(name, _)| hash_type.is_none_or(|hash_type| hash_type == *name))
+        .any(|(_, length)| *length == hash.len());
+    match valid_length {
+        true => Ok(hash.to_ascii_lowercase()),
+        false => Err(match hash_type {
+            Some(hash_type) => format!("length {} does not fit {hash_type}", hash.len()),
+            None => format!("length {} fits no md5, sha1 or sha256", hash.len()),
+        }),
+    }
+}